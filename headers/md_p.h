@@ -27,9 +27,9 @@
 #define MD_DISK_ACTIVE		1 /* disk is running but may not be in sync */
 #define MD_DISK_SYNC		2 /* disk is in sync with the raid set */
 #define MD_DISK_REMOVED		3 /* disk is in sync with the raid set */
-#define MD_DISK_CLUSTER_ADD	4 /* Initiate a disk add across the cluster
-#define MD_DISK_CANDIDATE	5 /* disk is added as spare (local) until confirmed
-#define	MD_DISK_WRITEMOSTLY	9 /* disk is "write-mostly" is RAID1 config.
+#define MD_DISK_CLUSTER_ADD	4 /* Initiate a disk add across the cluster */
+#define MD_DISK_CANDIDATE	5 /* disk is added as spare (local) until confirmed */
+#define	MD_DISK_WRITEMOSTLY	9 /* disk is "write-mostly" is RAID1 config. */
 #define	MD_DISK_FAILFAST	10 /* Fewer retries, more failures */
 #define MD_DISK_REPLACEMENT	17
 #define MD_DISK_JOURNAL		18 /* disk is used as the write journal in RAID-5/6 */
@@ -42,7 +42,7 @@
 #define MD_SB_ERRORS		1
 #define MD_SB_BBM_ERRORS	2
 #define MD_SB_BLOCK_CONTAINER_RESHAPE 3 /* block container wide reshapes */
-#define MD_SB_BLOCK_VOLUME	4 /* block activation of array, other arrays
+#define MD_SB_BLOCK_VOLUME	4 /* block activation of array, other arrays */
 #define MD_SB_CLUSTERED		5 /* MD is clustered  */
 #define	MD_SB_BITMAP_PRESENT	8 /* bitmap may be present nearby */
 #define R5LOG_VERSION 0x1
@@ -1,36 +1,88 @@
-use crate::{block, MdpSuperblock1};
-use anyhow::{anyhow, bail, Context, Result};
-use device_mapper::ioctl;
-use libc;
-use std::ffi::CString;
-use std::fs::OpenOptions;
-use std::io::Error;
+use crate::examine::{DEVFLAG_FAILFAST, DEVFLAG_WRITE_MOSTLY};
+use crate::{block, control, ioctl};
+use crate::{DeviceRole, FeatureMap, MdError, MdpSuperblock1, Result, Superblock};
+use std::collections::HashMap;
 use std::os::linux::fs::MetadataExt;
-use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::AsRawFd;
 use std::path::Path;
 
-const MD_MAJOR_DEV_ID: u32 = 9;
 struct DiskMeta {
-    superblock: MdpSuperblock1,
+    path: String,
+    superblock: Superblock,
     major: u32,
     minor: u32,
 }
 
-pub fn assemble_array(disk_paths: &[&str], md_dev_num: u32) -> Result<()> {
+/// A member whose event count is behind the rest of the array
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleMember {
+    pub path: String,
+    pub role: DeviceRole,
+    pub events: u64,
+}
+
+/// What `assemble_array` did with members that were not up to date
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AssembleReport {
+    /// Event count of the freshest member, the array is assembled at this count
+    pub events: u64,
+    /// Members left out of the array
+    pub excluded: Vec<StaleMember>,
+    /// Stale members brought up to `events` because the array couldn't start
+    /// without them and `force` was given
+    pub forced: Vec<StaleMember>,
+    /// Slots without a member, the array is degraded if there are any
+    pub missing: Vec<u32>,
+    /// Whether the array was started. A degraded array is left inactive unless
+    /// `run` is given, holding on to its members until it is stopped
+    pub started: bool,
+}
+
+/// Start the array made of `disk_paths` as md<md_dev_num>.
+///
+/// Members whose event count is behind the freshest one missed writes and are
+/// left out, as are older copies of a slot another member holds. With `force`,
+/// an array that lost too many members at once is brought back like
+/// `mdadm --assemble --force` does: the most recent stale member gets its event
+/// count raised, one at a time until the array can start. Each one costs the
+/// writes it missed, so no more are forced than needed.
+///
+/// Arrays missing more members than the level tolerates are refused. Arrays
+/// that are only degraded are set up but left inactive unless `run` is given,
+/// so a member that is late to show up isn't rebuilt from scratch. An inactive
+/// array can't be started or given more members here, stop it with
+/// `control::stop` and assemble it again.
+pub fn assemble_array(
+    disk_paths: &[&str],
+    md_dev_num: u32,
+    force: bool,
+    run: bool,
+) -> Result<AssembleReport> {
     // Read metadata from disks
     let mut meta = Vec::new();
     for path in disk_paths {
-        let md = std::fs::metadata(path).unwrap();
+        let md = std::fs::metadata(path)?;
         let is_block = block::is_block(Path::new(path))?;
         if !is_block {
-            bail!("{path} is not a block device, cannot be assembled")
+            return Err(MdError::NotBlockDevice {
+                path: path.to_string(),
+            });
         }
         let rdev = md.st_rdev();
         let major = unsafe { libc::major(rdev) }; // HOW IS THIS UNSAFE???
         let minor = unsafe { libc::minor(rdev) };
-        let sb = MdpSuperblock1::from_file(path, 0x1000)?;
+        let sb = Superblock::probe(path)?;
+        // the kernel needs the full geometry to resume a reshape, which we
+        // don't pass through SET_ARRAY_INFO; starting it as if the reshape
+        // was finished would read half the array with the wrong layout
+        if let Some(reshape) = sb.reshape() {
+            return Err(MdError::ReshapeInProgress {
+                path: path.to_string(),
+                position: reshape.reshape_position,
+            });
+        }
         meta.push(DiskMeta {
+            path: path.to_string(),
             superblock: sb,
             major,
             minor,
@@ -39,19 +91,34 @@ pub fn assemble_array(disk_paths: &[&str], md_dev_num: u32) -> Result<()> {
 
     // Validate that all superblocks belong to the same array
     let first_sb = &meta[0].superblock;
-    let first_uuid = first_sb.array_info.uuid();
-    for _meta in &meta[1..] {
-        if _meta.superblock.array_info.uuid() != first_uuid {
-            bail!("Disks do not belong to the same array");
+    let first_uuid = first_sb.uuid();
+    let first_version = (first_sb.major_version(), first_sb.minor_version());
+    for (_meta, path) in meta.iter().zip(disk_paths).skip(1) {
+        if _meta.superblock.uuid() != first_uuid {
+            return Err(MdError::ArrayMismatch {
+                path: path.to_string(),
+            });
+        }
+        let version = (
+            _meta.superblock.major_version(),
+            _meta.superblock.minor_version(),
+        );
+        if version != first_version {
+            return Err(MdError::VersionMismatch {
+                path: path.to_string(),
+            });
         }
     }
 
+    let mut report = settle_members(&mut meta, force)?;
+
     let array_info = ioctl::mdu_array_info_t {
-        major_version: 1,
-        minor_version: 2,
+        major_version: first_version.0 as i32,
+        minor_version: first_version.1 as i32,
         patch_version: 0,
-        // mdadm sets all of these to zero??
-        // what's the point??
+        // with raid_disks == 0 the kernel only records the superblock version
+        // and loads everything else from the members' superblocks. Filling
+        // these in would make it create a new array with a new uuid instead
         ctime: 0,
         utime: 0,
         md_minor: 0,
@@ -69,37 +136,9 @@ pub fn assemble_array(disk_paths: &[&str], md_dev_num: u32) -> Result<()> {
         chunk_size: 0,
     };
 
-    // Create a temporary device node
-
-    let tmp_path = "/tmp/_tmp_node_pls_no_clobber";
-    let tmp_c_path = CString::new(tmp_path)?;
-    if std::fs::metadata(&tmp_path).is_ok() {
-        std::fs::remove_file(&tmp_path).context("failed to delete tmp path")?;
-    }
-    unsafe {
-        // this 1 == md<1>
-        let dev = libc::makedev(MD_MAJOR_DEV_ID, md_dev_num);
-        if libc::mknod(tmp_c_path.as_ptr(), libc::S_IFBLK | 0o660, dev) != 0 {
-            return Err(anyhow!(
-                "Can't mknod {}: {}",
-                tmp_path,
-                std::io::Error::last_os_error()
-            ));
-        }
-    }
-
-    // Open the temporary device
-    let file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .mode(0o600)
-        .open(&tmp_path)
-        .context(format!("Can't get fd (open) from {}", tmp_path))?;
+    let file = control::open(md_dev_num)?;
     let fd = file.as_raw_fd();
 
-    // remove the file so no one else can touch it - we still have the fd open
-    std::fs::remove_file(&tmp_path).context("failed to delete tmp path")?;
-
     // if we previously did this half-way, then the array is
     // up but 'inactive' - we stop it blindly and ignore errors
     // should probably be cleaner
@@ -107,36 +146,462 @@ pub fn assemble_array(disk_paths: &[&str], md_dev_num: u32) -> Result<()> {
 
     // println!("{array_info:?}");
     // Set array info
-    let errno = unsafe { ioctl::set_array_info(fd, &array_info) };
-
-    if errno != 0 {
-        bail!("Can't set_array_info: {}", Error::from_raw_os_error(errno));
+    if unsafe { ioctl::set_array_info(fd, &array_info) } != 0 {
+        return Err(MdError::ioctl("set_array_info"));
     }
 
-    // Add disks to the array
-    for (i, meta) in meta.iter().enumerate() {
+    // Active members in slot order, then spares and faulty ones, regardless
+    // of the order they were given in
+    meta.sort_by_key(|meta| match meta.superblock.role() {
+        DeviceRole::Active(slot) => (0, slot),
+        _ => (1, 0),
+    });
+    for meta in &meta {
+        let (raid_disk, state) = disk_state(&meta.superblock);
         let disk_info = ioctl::mdu_disk_info_t {
             major: meta.major as i32,
             minor: meta.minor as i32,
-            number: i as i32,
-            raid_disk: i as i32,
-            // Assuming the disk is in a good state
-            state: (1 << ioctl::MD_DISK_SYNC) | (1 << ioctl::MD_DISK_ACTIVE),
+            number: meta.superblock.dev_number() as i32,
+            raid_disk,
+            state: state as i32,
+        };
+
+        if unsafe { ioctl::add_new_disk(fd, &disk_info) } != 0 {
+            return Err(MdError::ioctl("add_new_disk"));
+        }
+    }
+
+    // a degraded array stays inactive with its members claimed until it is
+    // stopped and assembled again, with the missing members or `run`
+    if !report.missing.is_empty() && !run {
+        return Ok(report);
+    }
+    if unsafe { ioctl::run_array(fd, std::ptr::null()) } != 0 {
+        return Err(MdError::ioctl("run_array"));
+    }
+    report.started = true;
+
+    Ok(report)
+}
+
+/// Decide which members to leave out or force, and check the rest can start
+/// the array before writing anything. `meta` is left with the members to
+/// add. Forced event counts and roles are only written once the array is
+/// known to start, a refused array keeps its superblocks as they were
+fn settle_members(meta: &mut Vec<DiskMeta>, force: bool) -> Result<AssembleReport> {
+    let sbs: Vec<&Superblock> = meta.iter().map(|meta| &meta.superblock).collect();
+    let (events, behind, duplicates) = find_stale(&sbs);
+    let forced = if force {
+        choose_forced(&sbs, &behind, &duplicates)
+    } else {
+        Vec::new()
+    };
+    let stale = |meta: &DiskMeta| StaleMember {
+        path: meta.path.clone(),
+        role: meta.superblock.role(),
+        events: meta.superblock.events(),
+    };
+    let mut report = AssembleReport {
+        events,
+        ..Default::default()
+    };
+    let mut excluded = duplicates;
+    excluded.extend(behind.into_iter().filter(|i| !forced.contains(i)));
+    excluded.sort();
+
+    // superblocks to write back
+    let mut changed = forced.clone();
+    let mut forced_roles = Vec::new();
+    for &i in &forced {
+        let meta = &mut meta[i];
+        report.forced.push(stale(meta));
+        forced_roles.push((meta.superblock.dev_number(), meta.superblock.role()));
+        meta.superblock.set_events(events);
+    }
+    for (i, meta) in meta.iter_mut().enumerate() {
+        if excluded.contains(&i) {
+            continue;
+        }
+        if let Superblock::V1(_, sb) = &mut meta.superblock {
+            if restore_roles(sb, &forced_roles) && !changed.contains(&i) {
+                changed.push(i);
+            }
+        }
+    }
+
+    let sbs: Vec<&Superblock> = (0..meta.len())
+        .filter(|i| !excluded.contains(i))
+        .map(|i| &meta[i].superblock)
+        .collect();
+    let freshest = freshest(&sbs);
+    let raid_disks = freshest.raid_disks();
+    let present = slots_present(&sbs);
+    report.missing = (0..raid_disks)
+        .filter(|&slot| !present[slot as usize])
+        .collect();
+    if !can_run(freshest, &present) {
+        return Err(MdError::NotEnoughMembers {
+            found: raid_disks - report.missing.len() as u32,
+            raid_disks,
+        });
+    }
+
+    for &i in &changed {
+        let meta = &mut meta[i];
+        meta.superblock.write(&meta.path)?;
+    }
+    for &i in excluded.iter().rev() {
+        report.excluded.insert(0, stale(&meta.remove(i)));
+    }
+    Ok(report)
+}
+
+/// Freshest event count, members more than one event behind it and members
+/// holding a slot that a fresher member also claims.
+///
+/// The kernel tolerates a difference of one event, the last update may not
+/// have reached every member before a crash. Spares hold no data so their
+/// event count doesn't matter. Between members claiming the same slot the
+/// higher event count wins, then the later update time.
+fn find_stale(sbs: &[&Superblock]) -> (u64, Vec<usize>, Vec<usize>) {
+    let freshness = |i: usize| (sbs[i].events(), sbs[i].update_time());
+    let mut duplicates = Vec::new();
+    let mut holders: HashMap<u16, usize> = HashMap::new();
+    for (i, sb) in sbs.iter().enumerate() {
+        let DeviceRole::Active(slot) = sb.role() else {
+            continue;
+        };
+        match holders.get(&slot) {
+            Some(&j) if freshness(j) >= freshness(i) => duplicates.push(i),
+            Some(&j) => {
+                duplicates.push(j);
+                holders.insert(slot, i);
+            }
+            None => {
+                holders.insert(slot, i);
+            }
+        }
+    }
+
+    let events = sbs.iter().map(|sb| sb.events()).max().unwrap_or(0);
+    let behind = (0..sbs.len())
+        .filter(|i| !duplicates.contains(i))
+        .filter(|&i| sbs[i].role() != DeviceRole::Spare && sbs[i].events() + 1 < events)
+        .collect();
+    (events, behind, duplicates)
+}
+
+/// The superblock the kernel goes by, the one with the highest event count
+/// and then the latest update time
+fn freshest<'a>(sbs: &[&'a Superblock]) -> &'a Superblock {
+    sbs.iter()
+        .max_by_key(|sb| (sb.events(), sb.update_time()))
+        .expect("the freshest member is never excluded")
+}
+
+/// Which of the freshest superblock's slots `sbs` fill
+fn slots_present(sbs: &[&Superblock]) -> Vec<bool> {
+    let mut present = vec![false; freshest(sbs).raid_disks() as usize];
+    for sb in sbs {
+        if let DeviceRole::Active(slot) = sb.role() {
+            if let Some(present) = present.get_mut(slot as usize) {
+                *present = true;
+            }
+        }
+    }
+    present
+}
+
+fn can_run(freshest: &Superblock, present: &[bool]) -> bool {
+    match freshest.level() {
+        Some(level) => level.can_run(freshest.layout(), present),
+        None => present.iter().all(|&present| present),
+    }
+}
+
+/// Members of `behind` to force into the array, as mdadm does: none if the
+/// array can start without them, otherwise the most recent one filling a
+/// missing slot, one at a time until it can
+fn choose_forced(sbs: &[&Superblock], behind: &[usize], duplicates: &[usize]) -> Vec<usize> {
+    let freshness = |i: usize| (sbs[i].events(), sbs[i].update_time());
+    let mut members: Vec<&Superblock> = (0..sbs.len())
+        .filter(|i| !behind.contains(i) && !duplicates.contains(i))
+        .map(|i| sbs[i])
+        .collect();
+    let mut candidates = behind.to_vec();
+    candidates.sort_by_key(|&i| std::cmp::Reverse(freshness(i)));
+
+    let mut forced = Vec::new();
+    for i in candidates {
+        let present = slots_present(&members);
+        if can_run(freshest(sbs), &present) {
+            break;
+        }
+        // a slot that is already filled doesn't help
+        match sbs[i].role() {
+            DeviceRole::Active(slot) if present.get(slot as usize) == Some(&false) => {}
+            _ => continue,
+        }
+        members.push(sbs[i]);
+        forced.push(i);
+    }
+    forced
+}
+
+/// Record the roles of the `forced` members, as (dev_number, role), in `sb`.
+/// The members that stayed may have marked them faulty when they dropped
+/// out, and the kernel trusts the freshest superblock. Returns whether
+/// anything changed
+fn restore_roles(sb: &mut MdpSuperblock1, forced: &[(u32, DeviceRole)]) -> bool {
+    let mut changed = false;
+    for &(dev_number, role) in forced {
+        if sb.role_of(dev_number) != role {
+            sb.set_role(dev_number, role);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// `raid_disk` and `state` bits for ADD_NEW_DISK, as mdadm's getinfo_super
+/// derives them from the member's own superblock
+fn disk_state(sb: &Superblock) -> (i32, u32) {
+    let sb = match sb {
+        // 0.90 stores the descriptor passed to the kernel as is
+        Superblock::V0_90(sb) => return (sb.this_disk.raid_disk as i32, sb.this_disk.state),
+        Superblock::V1(_, sb) => sb,
+    };
+    let mut flags = 0;
+    if sb.device_info.devflags & DEVFLAG_WRITE_MOSTLY != 0 {
+        flags |= 1 << ioctl::MD_DISK_WRITEMOSTLY;
+    }
+    if sb.device_info.devflags & DEVFLAG_FAILFAST != 0 {
+        flags |= 1 << ioctl::MD_DISK_FAILFAST;
+    }
+    let features = sb.array_info.features();
+    match sb.role() {
+        DeviceRole::Active(slot) => {
+            let mut state = flags | (1 << ioctl::MD_DISK_ACTIVE);
+            // a member that was being recovered is active but not in sync,
+            // the kernel resumes the recovery from recovery_offset
+            if !features.contains(FeatureMap::RECOVERY_OFFSET) {
+                state |= 1 << ioctl::MD_DISK_SYNC;
+            }
+            if features.contains(FeatureMap::REPLACEMENT) {
+                state |= 1 << ioctl::MD_DISK_REPLACEMENT;
+            }
+            (slot as i32, state)
+        }
+        DeviceRole::Spare => (-1, flags),
+        DeviceRole::Faulty => (-1, flags | (1 << ioctl::MD_DISK_FAULTY)),
+        DeviceRole::Journal => (
+            ioctl::MD_DISK_ROLE_JOURNAL as i32,
+            flags | (1 << ioctl::MD_DISK_JOURNAL),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ArrayLevel, SuperblockVersion};
+
+    /// Member `dev_number` of a 3 disk raid5
+    fn raid5_member(dev_number: u32) -> MdpSuperblock1 {
+        let config = crate::tests::config(ArrayLevel::Raid5, 3);
+        crate::tests::member(&config, 2048, dev_number).unwrap()
+    }
+
+    fn v1(sb: MdpSuperblock1) -> Superblock {
+        Superblock::V1(SuperblockVersion::V1_2, sb)
+    }
+
+    fn state_of(sb: MdpSuperblock1) -> (i32, u32) {
+        disk_state(&v1(sb))
+    }
+
+    #[test]
+    fn test_disk_state_from_roles() {
+        let in_sync = (1 << ioctl::MD_DISK_ACTIVE) | (1 << ioctl::MD_DISK_SYNC);
+        // the slot comes from dev_roles, not from dev_number
+        let mut sb = raid5_member(1);
+        sb.set_role(1, DeviceRole::Active(2));
+        assert_eq!(state_of(sb), (2, in_sync));
+
+        let mut sb = raid5_member(3);
+        sb.set_role(3, DeviceRole::Spare);
+        assert_eq!(state_of(sb), (-1, 0));
+
+        let mut sb = raid5_member(0);
+        sb.set_role(0, DeviceRole::Faulty);
+        sb.device_info.devflags = DEVFLAG_WRITE_MOSTLY;
+        assert_eq!(
+            state_of(sb),
+            (
+                -1,
+                (1 << ioctl::MD_DISK_FAULTY) | (1 << ioctl::MD_DISK_WRITEMOSTLY)
+            )
+        );
+
+        // half way through a recovery
+        let mut sb = raid5_member(2);
+        sb.array_info.set_features(FeatureMap::RECOVERY_OFFSET);
+        assert_eq!(state_of(sb), (2, 1 << ioctl::MD_DISK_ACTIVE));
+    }
+
+    #[test]
+    fn test_find_stale() {
+        let members: Vec<Superblock> = (0..4)
+            .map(|i| {
+                let mut sb = raid5_member(i);
+                sb.set_role(3, DeviceRole::Spare);
+                sb.array_state_info.events = [100, 99, 80, 10][i as usize];
+                v1(sb)
+            })
+            .collect();
+        let sbs: Vec<&Superblock> = members.iter().collect();
+        // one event behind is fine, the spare's count is ignored
+        assert_eq!(find_stale(&sbs), (100, vec![2], vec![]));
+
+        // an old copy of slot 0, whichever order it's found in
+        let mut old_copy = raid5_member(0);
+        old_copy.array_state_info.events = 100;
+        old_copy.array_state_info.utime = 0;
+        let old_copy = v1(old_copy);
+        let sbs = vec![&old_copy, &members[0], &members[1]];
+        assert_eq!(find_stale(&sbs), (100, vec![], vec![0]));
+        let sbs = vec![&members[0], &members[1], &old_copy];
+        assert_eq!(find_stale(&sbs), (100, vec![], vec![2]));
+    }
+
+    #[test]
+    fn test_choose_forced() {
+        // slot i of a 3 disk raid5 at each event count
+        let members = |events: &[u64]| -> Vec<Superblock> {
+            events
+                .iter()
+                .enumerate()
+                .map(|(i, &events)| {
+                    let mut sb = raid5_member(i as u32);
+                    sb.array_state_info.events = events;
+                    v1(sb)
+                })
+                .collect()
+        };
+        let choose = |members: &[Superblock]| {
+            let sbs: Vec<&Superblock> = members.iter().collect();
+            let (_, behind, duplicates) = find_stale(&sbs);
+            choose_forced(&sbs, &behind, &duplicates)
+        };
+        // degraded is enough to start, nothing is forced
+        assert_eq!(choose(&members(&[100, 100, 50])), vec![]);
+        // the most recent of two stale members, and only that one
+        assert_eq!(choose(&members(&[100, 50, 80])), vec![2]);
+        assert_eq!(choose(&members(&[100, 80, 50])), vec![1]);
+        // with slot 2 gone, the stale member is enough to run degraded
+        assert_eq!(choose(&members(&[100, 50])), vec![1]);
+
+        // a more recent member that isn't in a slot doesn't help
+        let mut members = members(&[100, 30, 80]);
+        let Superblock::V1(_, sb) = &mut members[2] else {
+            unreachable!()
         };
-        // println!("{disk_info:?}");
+        sb.set_role(2, DeviceRole::Faulty);
+        assert_eq!(choose(&members), vec![1]);
+    }
 
-        let errno = unsafe { ioctl::add_new_disk(fd, &disk_info) };
-        if errno != 0 {
-            bail!("Can't add_new_disk: {}", Error::from_raw_os_error(errno));
+    /// `sb` written to a new file named after `name`
+    fn on_disk(name: &str, sb: MdpSuperblock1) -> DiskMeta {
+        let path = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        std::fs::File::create(&path)
+            .unwrap()
+            .set_len(1 << 20)
+            .unwrap();
+        let path = path.to_str().unwrap().to_string();
+        let minor = sb.device_info.dev_number;
+        let mut superblock = v1(sb);
+        superblock.write(&path).unwrap();
+        DiskMeta {
+            path,
+            superblock,
+            major: 7,
+            minor,
         }
     }
 
-    // Run the array
-    let errno = unsafe { ioctl::run_array(fd, std::ptr::null()) };
+    fn read_all(meta: &[DiskMeta]) -> Vec<Vec<u8>> {
+        meta.iter()
+            .map(|meta| std::fs::read(&meta.path).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_settle_members() {
+        // slots 2 and 3 of a 4 disk raid5 are gone, slot 1 dropped out
+        // earlier and is marked faulty by slot 0
+        let config = crate::tests::config(ArrayLevel::Raid5, 4);
+        let mut fresh = crate::tests::member(&config, 2048, 0).unwrap();
+        fresh.array_state_info.events = 100;
+        fresh.set_role(1, DeviceRole::Faulty);
+        let mut stale = crate::tests::member(&config, 2048, 1).unwrap();
+        stale.array_state_info.events = 50;
+        let mut meta = vec![
+            on_disk("settle_refused_0", fresh),
+            on_disk("settle_refused_1", stale),
+        ];
+        let before = read_all(&meta);
+        let result = settle_members(&mut meta, true);
+        let after = read_all(&meta);
+        for meta in &meta {
+            std::fs::remove_file(&meta.path).unwrap();
+        }
+        assert!(matches!(
+            result,
+            Err(MdError::NotEnoughMembers {
+                found: 2,
+                raid_disks: 4
+            })
+        ));
+        // forcing slot 1 alone doesn't help, nothing was written
+        assert!(before == after);
 
-    if errno != 0 {
-        bail!("Can't run_array: {}", Error::from_raw_os_error(errno));
+        // slot 2 of a 3 disk raid5 is gone, slot 1 is brought back
+        let mut fresh = raid5_member(0);
+        fresh.array_state_info.events = 100;
+        fresh.set_role(1, DeviceRole::Faulty);
+        let mut stale = raid5_member(1);
+        stale.array_state_info.events = 50;
+        let mut meta = vec![
+            on_disk("settle_forced_0", fresh),
+            on_disk("settle_forced_1", stale),
+        ];
+        let report = settle_members(&mut meta, true).unwrap();
+        let probed: Vec<_> = meta
+            .iter()
+            .map(|meta| Superblock::probe(&meta.path).unwrap())
+            .collect();
+        for meta in &meta {
+            std::fs::remove_file(&meta.path).unwrap();
+        }
+        assert_eq!(report.forced.len(), 1);
+        assert_eq!(report.missing, vec![2]);
+        assert_eq!(probed[1].events(), 100);
+        let Superblock::V1(_, sb) = &probed[0] else {
+            unreachable!()
+        };
+        assert_eq!(sb.role_of(1), DeviceRole::Active(1));
     }
 
-    Ok(())
+    #[test]
+    fn test_restore_roles() {
+        // slot 2 dropped out and was marked faulty by the others
+        let mut sb = raid5_member(0);
+        sb.set_role(2, DeviceRole::Faulty);
+        assert!(restore_roles(&mut sb, &[(2, DeviceRole::Active(2))]));
+        assert_eq!(sb.role_of(2), DeviceRole::Active(2));
+        assert_eq!(sb.array_state(), "AAA");
+        // already recorded, nothing to write
+        assert!(!restore_roles(&mut sb, &[(2, DeviceRole::Active(2))]));
+        assert!(!restore_roles(&mut sb, &[]));
+    }
 }
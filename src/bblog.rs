@@ -0,0 +1,293 @@
+//! Bad block log, stored next to the v1.x superblock at `bblog_offset`.
+//!
+//! Each entry is a little endian u64 with the start sector in the top 54 bits and
+//! the length in the bottom 10, both in units of `1 << bblog_shift` sectors.
+//! Unused entries are all ones.
+use crate::{FeatureMap, MdError, MdpSuperblock1, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+const EMPTY_ENTRY: u64 = u64::MAX;
+/// Same limit the kernel uses for a single in-memory entry, BB_MAX_LEN
+const MAX_ENTRY_LEN: u64 = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BadBlock {
+    /// First bad sector, in 512b sectors
+    pub sector: u64,
+    /// In 512b sectors
+    pub length: u64,
+    /// Whether the entry is persisted in the on-disk log. Entries read from
+    /// disk are always acknowledged; new entries are not until the log is written.
+    pub acknowledged: bool,
+}
+
+impl BadBlock {
+    fn end(&self) -> u64 {
+        self.sector + self.length
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BadBlockLog {
+    /// Entries are stored in units of `1 << shift` sectors
+    pub shift: u8,
+    /// Number of entries that fit in the on-disk log
+    capacity: usize,
+    /// Sorted, non-overlapping
+    entries: Vec<BadBlock>,
+}
+
+impl BadBlockLog {
+    pub fn new(shift: u8, size_sectors: u16) -> Self {
+        BadBlockLog {
+            shift,
+            capacity: size_sectors as usize * 512 / 8,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn from_bytes(buf: &[u8], shift: u8) -> Self {
+        let entries = buf
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .take_while(|&entry| entry != EMPTY_ENTRY)
+            .map(|entry| BadBlock {
+                sector: (entry >> 10) << shift,
+                length: (entry & 0x3ff) << shift,
+                acknowledged: true,
+            })
+            .collect();
+        let mut log = BadBlockLog {
+            shift,
+            capacity: buf.len() / 8,
+            entries,
+        };
+        log.entries.sort_by_key(|bb| bb.sector);
+        log
+    }
+
+    /// Serialized entries, padded with empty entries to the log capacity.
+    /// Ranges longer than a single entry can hold are split.
+    pub fn as_bytes(&self) -> Result<Vec<u8>> {
+        let mut ret = Vec::with_capacity(self.capacity * 8);
+        for bb in &self.entries {
+            let mut start = bb.sector >> self.shift;
+            let end = bb.end().div_ceil(1 << self.shift);
+            while start < end {
+                let len = (end - start).min(MAX_ENTRY_LEN);
+                ret.extend_from_slice(&((start << 10) | len).to_le_bytes());
+                start += len;
+            }
+        }
+        if ret.len() > self.capacity * 8 {
+            return Err(MdError::BadBlockLogFull {
+                capacity: self.capacity,
+            });
+        }
+        ret.resize(self.capacity * 8, 0xff);
+        Ok(ret)
+    }
+
+    /// Read the log of the superblock stored on `path`. Empty if the superblock
+    /// does not have any bad blocks recorded.
+    pub fn from_superblock(path: &str, sb: &MdpSuperblock1) -> Result<Self> {
+        let shift = sb.device_info.bblog_shift;
+        let size = sb.device_info.bblog_size;
+        let Some(location) = sb.bblog_location() else {
+            return Err(MdError::NoBadBlockLog {
+                path: path.to_string(),
+            });
+        };
+        if !sb.array_info.features().contains(FeatureMap::BAD_BLOCKS) {
+            // the log contents are not meaningful without the feature bit
+            return Ok(Self::new(shift, size));
+        }
+
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(location))?;
+        let mut buf = vec![0; size as usize * 512];
+        file.read_exact(&mut buf)?;
+        Ok(Self::from_bytes(&buf, shift))
+    }
+
+    pub fn entries(&self) -> &[BadBlock] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Record a range as bad. It's rounded out to the log granularity, and
+    /// merged with adjacent ranges.
+    pub fn add(&mut self, sector: u64, length: u64) {
+        let unit = 1u64 << self.shift;
+        let start = sector / unit * unit;
+        let end = (sector + length).div_ceil(unit) * unit;
+        self.clear(start, end - start);
+
+        let mut new = BadBlock {
+            sector: start,
+            length: end - start,
+            acknowledged: false,
+        };
+        // absorb neighbours that touch the new range
+        self.entries.retain(|bb| {
+            let touches = bb.end() == new.sector || bb.sector == new.end();
+            if touches {
+                let end = new.end().max(bb.end());
+                new.sector = new.sector.min(bb.sector);
+                new.length = end - new.sector;
+            }
+            !touches
+        });
+        let idx = self.entries.partition_point(|bb| bb.sector < new.sector);
+        self.entries.insert(idx, new);
+    }
+
+    /// Forget a range, splitting entries that only partially overlap it
+    pub fn clear(&mut self, sector: u64, length: u64) {
+        let end = sector + length;
+        let mut remaining = Vec::with_capacity(self.entries.len() + 1);
+        for bb in self.entries.drain(..) {
+            if bb.end() <= sector || bb.sector >= end {
+                remaining.push(bb);
+                continue;
+            }
+            if bb.sector < sector {
+                remaining.push(BadBlock {
+                    length: sector - bb.sector,
+                    ..bb
+                });
+            }
+            if bb.end() > end {
+                remaining.push(BadBlock {
+                    sector: end,
+                    length: bb.end() - end,
+                    ..bb
+                });
+            }
+        }
+        self.entries = remaining;
+    }
+
+    /// Write the log and the superblock back to `path`, setting or clearing
+    /// the bad blocks feature bit depending on whether the log has entries
+    pub fn write(&mut self, path: &str, sb: &mut MdpSuperblock1) -> Result<()> {
+        let Some(location) = sb.bblog_location() else {
+            return Err(MdError::NoBadBlockLog {
+                path: path.to_string(),
+            });
+        };
+        let bytes = self.as_bytes()?;
+
+        let mut features = sb.array_info.features();
+        features.set(FeatureMap::BAD_BLOCKS, !self.is_empty());
+        sb.array_info.set_features(features);
+        sb.update_sb_csum();
+
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.seek(SeekFrom::Start(location))?;
+        file.write_all(&bytes)?;
+        file.seek(SeekFrom::Start(sb.device_info.super_offset * 512))?;
+        file.write_all(&sb.as_bytes())?;
+        file.sync_all()?;
+
+        for bb in self.entries.iter_mut() {
+            bb.acknowledged = true;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_entries() {
+        let mut buf = vec![0xff; 4096];
+        buf[0..8].copy_from_slice(&((100u64 << 10) | 8).to_le_bytes());
+        buf[8..16].copy_from_slice(&((2048u64 << 10) | 1).to_le_bytes());
+        let log = BadBlockLog::from_bytes(&buf, 0);
+        assert_eq!(
+            log.entries(),
+            &[
+                BadBlock {
+                    sector: 100,
+                    length: 8,
+                    acknowledged: true
+                },
+                BadBlock {
+                    sector: 2048,
+                    length: 1,
+                    acknowledged: true
+                },
+            ]
+        );
+        assert_eq!(log.as_bytes().unwrap(), buf);
+
+        // with a shift, entries are in units of 8 sectors
+        let log = BadBlockLog::from_bytes(&buf, 3);
+        assert_eq!(log.entries()[0].sector, 800);
+        assert_eq!(log.entries()[0].length, 64);
+    }
+
+    #[test]
+    fn test_add_merges_and_clear_splits() {
+        let mut log = BadBlockLog::new(0, 8);
+        log.add(10, 10);
+        log.add(20, 5);
+        log.add(100, 1);
+        log.add(15, 2);
+        let ranges: Vec<(u64, u64)> = log
+            .entries()
+            .iter()
+            .map(|bb| (bb.sector, bb.length))
+            .collect();
+        assert_eq!(ranges, vec![(10, 15), (100, 1)]);
+        assert!(!log.entries()[0].acknowledged);
+
+        log.clear(12, 3);
+        let ranges: Vec<(u64, u64)> = log
+            .entries()
+            .iter()
+            .map(|bb| (bb.sector, bb.length))
+            .collect();
+        assert_eq!(ranges, vec![(10, 2), (15, 10), (100, 1)]);
+
+        log.clear(0, 1000);
+        assert!(log.is_empty());
+    }
+
+    #[test]
+    fn test_long_ranges_are_split() {
+        let mut log = BadBlockLog::new(0, 8);
+        log.add(0, 1000);
+        let bytes = log.as_bytes().unwrap();
+        let reparsed = BadBlockLog::from_bytes(&bytes, 0);
+        assert_eq!(reparsed.entries().len(), 2);
+        assert_eq!(reparsed.entries()[0].length, 512);
+        assert_eq!(reparsed.entries()[1].sector, 512);
+        assert_eq!(reparsed.entries()[1].length, 488);
+    }
+
+    #[test]
+    fn test_shift_rounds_out() {
+        let mut log = BadBlockLog::new(3, 8);
+        log.add(10, 1);
+        assert_eq!(log.entries()[0].sector, 8);
+        assert_eq!(log.entries()[0].length, 8);
+    }
+
+    #[test]
+    fn test_full_log() {
+        // 1 sector holds 64 entries
+        let mut log = BadBlockLog::new(0, 1);
+        for i in 0..65 {
+            log.add(i * 10, 1);
+        }
+        assert!(log.as_bytes().is_err());
+    }
+}
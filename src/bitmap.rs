@@ -0,0 +1,350 @@
+//! Write-intent bitmap superblock (bitmap_super_t), stored next to the md superblock
+//! at `bitmap_offset` when the array has an internal bitmap.
+use crate::{MdError, MdpSuperblock1, Result};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use uuid::Uuid;
+
+/// "bitm"
+const BITMAP_MAGIC: u32 = 0x6d746962;
+/// Oldest on-disk version the kernel still understands, BITMAP_MAJOR_LO
+const BITMAP_MAJOR_LO: u32 = 3;
+/// Version written by mdadm for new bitmaps, BITMAP_MAJOR_HI
+const BITMAP_MAJOR_HI: u32 = 4;
+/// Version used by clustered bitmaps, BITMAP_MAJOR_CLUSTERED
+const BITMAP_MAJOR_CLUSTERED: u32 = 5;
+/// mdadm won't pick a smaller chunk for internal bitmaps
+const MIN_INTERNAL_CHUNK: u32 = 64 * 1024 * 1024;
+/// Seconds between bitmap flushes, same default as mdadm
+const DEFAULT_DAEMON_SLEEP: u32 = 5;
+
+/// Space reserved for a bitmap on a device, in 512b sectors.
+/// Same as mdadm's choose_bm_space; the space is reserved even when the array is
+/// created without a bitmap, so one can be added later.
+pub fn reserved_sectors(device_size_sectors: u64) -> u64 {
+    if device_size_sectors < 64 * 2 {
+        0
+    } else if device_size_sectors - 64 * 2 >= 200 * 1024 * 1024 * 2 {
+        // over 200GiB, 128KiB
+        128 * 2
+    } else if device_size_sectors - 4 * 2 > 8 * 1024 * 1024 * 2 {
+        // over 8GiB, 64KiB
+        64 * 2
+    } else {
+        4 * 2
+    }
+}
+
+/// State of the bits in a write-intent bitmap, as shown by `mdadm --examine-bitmap`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitmapReport {
+    /// Total number of bits in the bitmap
+    pub chunks: u64,
+    /// Bytes of data covered by each bit
+    pub chunksize: u32,
+    /// Index of every set bit
+    pub dirty_chunks: Vec<u64>,
+    /// Sectors of each component that would be resynced, adjacent dirty chunks merged
+    pub resync_ranges: Vec<Range<u64>>,
+}
+
+impl BitmapReport {
+    pub fn dirty_percent(&self) -> f64 {
+        if self.chunks == 0 {
+            return 0.0;
+        }
+        self.dirty_chunks.len() as f64 * 100.0 / self.chunks as f64
+    }
+    /// Sectors that would be resynced, in 512b sectors
+    pub fn resync_sectors(&self) -> u64 {
+        self.resync_ranges.iter().map(|r| r.end - r.start).sum()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BitmapSuperblock {
+    pub version: u32,
+    uuid: [u8; 16],
+    pub events: u64,         // event count when the bitmap was last updated
+    pub events_cleared: u64, // event count when the bitmap was last cleared
+    pub sync_size: u64,      // size of the area covered by the bitmap, in 512b sectors
+    pub state: u32,
+    pub chunksize: u32,    // bytes of data covered by each bit
+    pub daemon_sleep: u32, // seconds between flushes
+    pub write_behind: u32, // max outstanding write-behind writes
+    pub sectors_reserved: u32,
+    pub nodes: u32,
+    cluster_name: [u8; 64],
+}
+
+impl BitmapSuperblock {
+    pub const SIZE: usize = 256;
+
+    pub fn new(uuid: Uuid, events: u64, sync_size: u64, chunksize: u32) -> Self {
+        BitmapSuperblock {
+            version: BITMAP_MAJOR_HI,
+            uuid: uuid.into_bytes(),
+            events,
+            events_cleared: events,
+            sync_size,
+            state: 0,
+            chunksize,
+            daemon_sleep: DEFAULT_DAEMON_SLEEP,
+            write_behind: 0,
+            sectors_reserved: 0,
+            nodes: 0,
+            cluster_name: [0; 64],
+        }
+    }
+
+    /// Smallest chunk (at least 64MiB) for which one bit per chunk of `sync_size`
+    /// fits in `room_sectors` after the bitmap superblock
+    pub fn choose_chunksize(sync_size: u64, room_sectors: u64) -> Option<u32> {
+        let max_bits = (room_sectors * 512).checked_sub(Self::SIZE as u64)? * 8;
+        if max_bits == 0 {
+            return None;
+        }
+        let mut chunksize = MIN_INTERNAL_CHUNK as u64;
+        while (sync_size * 512).div_ceil(chunksize) > max_bits {
+            chunksize *= 2;
+        }
+        u32::try_from(chunksize).ok()
+    }
+
+    /// Number of bits in the bitmap, one per chunk
+    pub fn chunks(&self) -> u64 {
+        if self.chunksize == 0 {
+            return 0;
+        }
+        (self.sync_size * 512).div_ceil(self.chunksize as u64)
+    }
+
+    /// Bytes used by the bitmap bits, which follow the superblock
+    pub fn bits_len(&self) -> usize {
+        self.chunks().div_ceil(8) as usize
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        Uuid::from_slice(&self.uuid).unwrap()
+    }
+
+    /// Name of the cluster for clustered arrays (`nodes > 0`)
+    pub fn cluster_name(&self) -> String {
+        let len = self.cluster_name.iter().position(|&b| b == 0).unwrap_or(64);
+        String::from_utf8_lossy(&self.cluster_name[..len]).into_owned()
+    }
+
+    pub fn as_bytes(&self) -> [u8; Self::SIZE] {
+        let mut buf = [0u8; Self::SIZE];
+        buf[0..4].copy_from_slice(&BITMAP_MAGIC.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.version.to_le_bytes());
+        buf[8..24].copy_from_slice(&self.uuid);
+        buf[24..32].copy_from_slice(&self.events.to_le_bytes());
+        buf[32..40].copy_from_slice(&self.events_cleared.to_le_bytes());
+        buf[40..48].copy_from_slice(&self.sync_size.to_le_bytes());
+        buf[48..52].copy_from_slice(&self.state.to_le_bytes());
+        buf[52..56].copy_from_slice(&self.chunksize.to_le_bytes());
+        buf[56..60].copy_from_slice(&self.daemon_sleep.to_le_bytes());
+        buf[60..64].copy_from_slice(&self.write_behind.to_le_bytes());
+        buf[64..68].copy_from_slice(&self.sectors_reserved.to_le_bytes());
+        buf[68..72].copy_from_slice(&self.nodes.to_le_bytes());
+        buf[72..136].copy_from_slice(&self.cluster_name);
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        if buf.len() < Self::SIZE {
+            return Err(MdError::Truncated {
+                what: "BitmapSuperblock",
+            });
+        }
+        let u32_at = |o: usize| u32::from_le_bytes(buf[o..o + 4].try_into().unwrap());
+        let u64_at = |o: usize| u64::from_le_bytes(buf[o..o + 8].try_into().unwrap());
+
+        let magic = u32_at(0);
+        if magic != BITMAP_MAGIC {
+            return Err(MdError::BadMagic {
+                what: "bitmap",
+                found: magic,
+            });
+        }
+        let version = u32_at(4);
+        if !(BITMAP_MAJOR_LO..=BITMAP_MAJOR_CLUSTERED).contains(&version) {
+            return Err(MdError::UnsupportedVersion {
+                what: "bitmap",
+                found: version,
+            });
+        }
+
+        Ok(BitmapSuperblock {
+            version,
+            uuid: buf[8..24].try_into().unwrap(),
+            events: u64_at(24),
+            events_cleared: u64_at(32),
+            sync_size: u64_at(40),
+            state: u32_at(48),
+            chunksize: u32_at(52),
+            daemon_sleep: u32_at(56),
+            write_behind: u32_at(60),
+            sectors_reserved: u32_at(64),
+            nodes: u32_at(68),
+            cluster_name: buf[72..136].try_into().unwrap(),
+        })
+    }
+
+    pub fn from_file(path: &str, offset: u64) -> Result<Self> {
+        let mut file = File::open(path)?;
+
+        file.seek(SeekFrom::Start(offset))?;
+        let mut buf = [0; Self::SIZE];
+        file.read_exact(&mut buf)?;
+
+        Self::from_bytes(&buf)
+    }
+
+    /// Read the bits that follow the bitmap superblock at `offset`
+    pub fn read_bits(&self, path: &str, offset: u64) -> Result<Vec<u8>> {
+        let mut file = File::open(path)?;
+
+        file.seek(SeekFrom::Start(offset + Self::SIZE as u64))?;
+        let mut bits = vec![0; self.bits_len()];
+        file.read_exact(&mut bits)?;
+        Ok(bits)
+    }
+
+    /// Decode the on-disk bits, bit N of the bitmap is bit N%8 of byte N/8
+    pub fn analyze(&self, bits: &[u8]) -> BitmapReport {
+        let chunks = self.chunks();
+        let chunk_sectors = self.chunksize as u64 / 512;
+        let dirty_chunks: Vec<u64> = (0..chunks)
+            .filter(|&chunk| {
+                bits.get((chunk / 8) as usize)
+                    .is_some_and(|byte| byte & (1 << (chunk % 8)) != 0)
+            })
+            .collect();
+
+        let mut resync_ranges: Vec<Range<u64>> = Vec::new();
+        for &chunk in &dirty_chunks {
+            let start = chunk * chunk_sectors;
+            // the last chunk can extend past the end of the array
+            let end = (start + chunk_sectors).min(self.sync_size);
+            match resync_ranges.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => resync_ranges.push(start..end),
+            }
+        }
+
+        BitmapReport {
+            chunks,
+            chunksize: self.chunksize,
+            dirty_chunks,
+            resync_ranges,
+        }
+    }
+
+    /// Superblock followed by the bits, padded to 4KiB.
+    /// All bits are set, so the whole array is resynced on first assembly.
+    pub fn initial_bytes(&self) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(4096);
+        ret.extend_from_slice(&self.as_bytes());
+        ret.resize(Self::SIZE + self.bits_len(), 0xff);
+        ret.resize(ret.len().next_multiple_of(4096), 0);
+        ret
+    }
+}
+
+/// Locate the internal bitmap of the md member at `path` and decode its bits
+pub fn examine_bitmap(path: &str) -> Result<(BitmapSuperblock, BitmapReport)> {
+    let (_, sb) = MdpSuperblock1::probe(path)?;
+    let Some(location) = sb.bitmap_location() else {
+        return Err(MdError::NoBitmap {
+            path: path.to_string(),
+        });
+    };
+    let bitmap = BitmapSuperblock::from_file(path, location)?;
+    let bits = bitmap.read_bits(path, location)?;
+    let report = bitmap.analyze(&bits);
+    Ok((bitmap, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let uuid = Uuid::new_v4();
+        let bsb = BitmapSuperblock::new(uuid, 18, 18432, 64 * 1024 * 1024);
+        let bytes = bsb.as_bytes();
+        assert_eq!(&bytes[0..4], b"bitm");
+
+        let parsed = BitmapSuperblock::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.uuid(), uuid);
+        assert_eq!(parsed.version, 4);
+        assert_eq!(parsed.events, 18);
+        assert_eq!(parsed.events_cleared, 18);
+        assert_eq!(parsed.sync_size, 18432);
+        assert_eq!(parsed.chunksize, 64 * 1024 * 1024);
+        assert_eq!(parsed.daemon_sleep, 5);
+        assert_eq!(parsed.chunks(), 1);
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        assert!(BitmapSuperblock::from_bytes(&[0u8; 256]).is_err());
+    }
+
+    #[test]
+    fn test_chunksize_fits_in_reserved_space() {
+        // 1TiB component, 128KiB reserved
+        let sync_size = 2 * 1024 * 1024 * 1024;
+        let room = reserved_sectors(sync_size + 4096);
+        assert_eq!(room, 256);
+        let chunksize = BitmapSuperblock::choose_chunksize(sync_size, room).unwrap();
+        assert_eq!(chunksize, 64 * 1024 * 1024);
+
+        // 4KiB reserved on small devices leaves 3840 bytes of bits
+        let sync_size = 16 * 1024 * 1024 * 1024 * 2; // 16TiB
+        let chunksize = BitmapSuperblock::choose_chunksize(sync_size, 8).unwrap();
+        let bsb = BitmapSuperblock::new(Uuid::nil(), 0, sync_size, chunksize);
+        assert!(bsb.bits_len() <= 4096 - BitmapSuperblock::SIZE);
+        assert_eq!(chunksize, 1024 * 1024 * 1024);
+        assert_eq!(bsb.initial_bytes().len(), 4096);
+    }
+
+    #[test]
+    fn test_analyze_bits() {
+        // 10 chunks of 64MiB, the last one partial
+        let chunk_sectors = 64 * 1024 * 2;
+        let sync_size = 9 * chunk_sectors + 100;
+        let bsb = BitmapSuperblock::new(Uuid::nil(), 0, sync_size, 64 * 1024 * 1024);
+        assert_eq!(bsb.chunks(), 10);
+        assert_eq!(bsb.bits_len(), 2);
+
+        // chunks 1, 2, 3, 5 and 9
+        let report = bsb.analyze(&[0b0010_1110, 0b0000_0010]);
+        assert_eq!(report.dirty_chunks, vec![1, 2, 3, 5, 9]);
+        assert_eq!(report.dirty_percent(), 50.0);
+        assert_eq!(
+            report.resync_ranges,
+            vec![
+                chunk_sectors..4 * chunk_sectors,
+                5 * chunk_sectors..6 * chunk_sectors,
+                9 * chunk_sectors..sync_size,
+            ]
+        );
+        assert_eq!(report.resync_sectors(), 4 * chunk_sectors + 100);
+    }
+
+    #[test]
+    fn test_initial_bitmap_is_all_dirty() {
+        let bsb = BitmapSuperblock::new(Uuid::nil(), 0, 1024 * 1024 * 2 * 10, 64 * 1024 * 1024);
+        let bytes = bsb.initial_bytes();
+        let parsed = BitmapSuperblock::from_bytes(&bytes).unwrap();
+        let report = parsed.analyze(&bytes[BitmapSuperblock::SIZE..]);
+        assert_eq!(report.chunks, 160);
+        assert_eq!(report.dirty_percent(), 100.0);
+        assert_eq!(report.resync_ranges, vec![0..parsed.sync_size]);
+    }
+}
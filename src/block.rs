@@ -1,7 +1,8 @@
-use anyhow::{bail, Context, Result};
-use device_mapper::ioctl::blkgetsize64;
-use std::fs::OpenOptions;
+use crate::ioctl::blkgetsize64;
+use crate::{MdError, Result};
+use std::fs::{File, OpenOptions};
 use std::os::linux::fs::MetadataExt;
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::AsRawFd;
 use std::path::Path;
 
@@ -15,17 +16,35 @@ pub fn get_size(path: &Path) -> Result<u64> {
         return Ok(metadata.len());
     }
     if !is_block(path)? {
-        bail!("Not a file and not a block device");
+        return Err(MdError::NotBlockDevice {
+            path: path.display().to_string(),
+        });
     }
 
-    let file = OpenOptions::new()
-        .read(true)
-        .open(path)
-        .context(format!("Can't get fd (open) from {:?}", path))?;
+    let file = OpenOptions::new().read(true).open(path)?;
     let fd = file.as_raw_fd();
     let mut size: u64 = 0;
     let size_ptr = &mut size as *mut u64;
 
-    unsafe { blkgetsize64(fd, size_ptr) };
+    if unsafe { blkgetsize64(fd, size_ptr) } < 0 {
+        return Err(MdError::ioctl("BLKGETSIZE64"));
+    }
     Ok(size)
 }
+
+/// Open `path` for writing. Block devices are opened with `O_EXCL`, which the
+/// kernel refuses while the device is mounted, part of a running array or
+/// otherwise claimed
+pub fn open_exclusive(path: &Path) -> Result<File> {
+    let mut options = OpenOptions::new();
+    options.read(true).write(true);
+    if is_block(path)? {
+        options.custom_flags(libc::O_EXCL);
+    }
+    options.open(path).map_err(|err| match err.raw_os_error() {
+        Some(libc::EBUSY) => MdError::DeviceBusy {
+            path: path.display().to_string(),
+        },
+        _ => err.into(),
+    })
+}
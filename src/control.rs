@@ -0,0 +1,55 @@
+//! Operations on md devices known to the kernel, by md minor number.
+use crate::{ioctl, MdError, Result};
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+const MD_MAJOR_DEV_ID: u32 = 9;
+
+/// Open md<md_minor>, whether or not udev created a node for it yet
+pub fn open(md_minor: u32) -> Result<File> {
+    // Create a temporary device node
+    let tmp_path = "/tmp/_tmp_node_pls_no_clobber";
+    let tmp_c_path = CString::new(tmp_path).unwrap();
+    if std::fs::metadata(tmp_path).is_ok() {
+        std::fs::remove_file(tmp_path)?;
+    }
+    unsafe {
+        let dev = libc::makedev(MD_MAJOR_DEV_ID, md_minor);
+        if libc::mknod(tmp_c_path.as_ptr(), libc::S_IFBLK | 0o660, dev) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+
+    // Open the temporary device
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .mode(0o600)
+        .open(tmp_path)?;
+
+    // remove the file so no one else can touch it - we still have the fd open
+    std::fs::remove_file(tmp_path)?;
+    Ok(file)
+}
+
+/// Stop md<md_minor>, releasing its member devices
+pub fn stop(md_minor: u32) -> Result<()> {
+    let file = open(md_minor)?;
+    if unsafe { ioctl::stop_array(file.as_raw_fd()) } != 0 {
+        return Err(MdError::ioctl("stop_array"));
+    }
+    Ok(())
+}
+
+/// Array information of a running array, as reported by the kernel
+pub fn array_info(md_minor: u32) -> Result<ioctl::mdu_array_info_t> {
+    let file = open(md_minor)?;
+    // plain C struct of ints, all zeroes is a valid value
+    let mut info: ioctl::mdu_array_info_t = unsafe { std::mem::zeroed() };
+    if unsafe { ioctl::get_array_info(file.as_raw_fd(), &mut info) } != 0 {
+        return Err(MdError::ioctl("get_array_info"));
+    }
+    Ok(info)
+}
@@ -0,0 +1,253 @@
+//! Creating new arrays: writing fresh superblocks to the member devices.
+use crate::{
+    block, zero, ArrayConfig, ArrayLevel, DeviceInfo, MdError, MdpSuperblock1, Result, Superblock,
+    SuperblockVersion,
+};
+use chrono::Utc;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use uuid::Uuid;
+
+/// Below this, `mdadm --examine` doesn't find the superblock. Only artificial
+/// cases using files are this small
+const MIN_DEVICE_SIZE: u64 = 10 * 1024;
+
+/// Magic numbers of things worth not overwriting: (name, byte offset, magic)
+const SIGNATURES: &[(&str, u64, &[u8])] = &[
+    ("an ext2/3/4 filesystem", 1080, &[0x53, 0xef]),
+    ("an XFS filesystem", 0, b"XFSB"),
+    ("a btrfs filesystem", 65600, b"_BHRfS_M"),
+    ("a LUKS header", 0, b"LUKS\xba\xbe"),
+    ("an LVM2 physical volume", 536, b"LVM2 001"),
+    ("swap space", 4086, b"SWAPSPACE2"),
+    ("a partition table", 510, &[0x55, 0xaa]),
+];
+
+/// Settings for a new array, written to the members with [`ArrayBuilder::create`].
+///
+/// ```no_run
+/// use device_mapper::{create::ArrayBuilder, ArrayLevel, Raid10Layout};
+///
+/// let superblocks = ArrayBuilder::new(ArrayLevel::Raid10, "data")
+///     .layout(Raid10Layout::far(2))
+///     .chunk_kib(256)
+///     .internal_bitmap(true)
+///     .create(&["/dev/sdb", "/dev/sdc", "/dev/sdd", "/dev/sde"])?;
+/// # Ok::<(), device_mapper::MdError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ArrayBuilder {
+    level: ArrayLevel,
+    name: String,
+    homehost: Option<String>,
+    uuid: Option<Uuid>,
+    version: SuperblockVersion,
+    layout: Option<u32>,
+    chunk_kib: Option<u32>,
+    data_offset: Option<u64>,
+    component_size: Option<u64>,
+    internal_bitmap: bool,
+    force: bool,
+}
+
+impl ArrayBuilder {
+    /// A v1.2 array with the level's default layout and chunk size, named
+    /// `<hostname>:<name>`
+    pub fn new(level: ArrayLevel, name: &str) -> Self {
+        ArrayBuilder {
+            level,
+            name: name.to_string(),
+            homehost: None,
+            uuid: None,
+            version: SuperblockVersion::V1_2,
+            layout: None,
+            chunk_kib: None,
+            data_offset: None,
+            component_size: None,
+            internal_bitmap: false,
+            force: false,
+        }
+    }
+
+    /// Host stored in the array name, defaults to the hostname
+    pub fn homehost(mut self, homehost: &str) -> Self {
+        self.homehost = Some(homehost.to_string());
+        self
+    }
+
+    /// Array uuid, random by default
+    pub fn uuid(mut self, uuid: Uuid) -> Self {
+        self.uuid = Some(uuid);
+        self
+    }
+
+    pub fn metadata(mut self, version: SuperblockVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// An `ArrayLayout` for raid4/5/6 or a `Raid10Layout` for raid10
+    pub fn layout(mut self, layout: impl Into<u32>) -> Self {
+        self.layout = Some(layout.into());
+        self
+    }
+
+    pub fn chunk_kib(mut self, kib: u32) -> Self {
+        self.chunk_kib = Some(kib);
+        self
+    }
+
+    /// Start of the data on each member in 512b sectors, see
+    /// `SuperblockVersion::data_offset`
+    pub fn data_offset(mut self, sectors: u64) -> Self {
+        self.data_offset = Some(sectors);
+        self
+    }
+
+    /// Space used on each member in 512b sectors, all of the smallest member
+    /// by default
+    pub fn component_size(mut self, sectors: u64) -> Self {
+        self.component_size = Some(sectors);
+        self
+    }
+
+    pub fn internal_bitmap(mut self, internal_bitmap: bool) -> Self {
+        self.internal_bitmap = internal_bitmap;
+        self
+    }
+
+    /// Overwrite devices that already hold md metadata or a filesystem
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Write superblocks (and the bitmap) to `devices`, in role order.
+    ///
+    /// Every superblock is prepared before anything is written, so invalid
+    /// settings leave the devices untouched. Unless forced, devices holding md
+    /// metadata or a known filesystem signature are refused. When forced, md
+    /// metadata of every version is zeroed before the new superblocks are
+    /// written. Returns the superblocks as written.
+    pub fn create(&self, devices: &[&str]) -> Result<Vec<MdpSuperblock1>> {
+        let mut sizes = Vec::with_capacity(devices.len());
+        for dev in devices {
+            let size = block::get_size(Path::new(dev))?;
+            if size < MIN_DEVICE_SIZE {
+                return Err(MdError::DeviceTooSmall {
+                    path: dev.to_string(),
+                    size,
+                });
+            }
+            if !self.force {
+                if let Some(what) = existing_signature(dev)? {
+                    return Err(MdError::ExistingSignature {
+                        path: dev.to_string(),
+                        what,
+                    });
+                }
+            }
+            sizes.push(size);
+        }
+        let min_size = sizes.iter().copied().min().unwrap_or(0);
+        let chunk_sectors = self.level.chunk_sectors(self.chunk_kib)?;
+        let member_sectors: Vec<u64> = sizes.iter().map(|size| size / 512).collect();
+        let data_offset =
+            self.version
+                .data_offset(&member_sectors, chunk_sectors, self.data_offset)?;
+        let homehost = match &self.homehost {
+            Some(host) => host.clone(),
+            None => hostname()?,
+        };
+        let config = ArrayConfig {
+            host: homehost,
+            name: self.name.clone(),
+            uuid: self.uuid.unwrap_or_else(Uuid::new_v4),
+            creation: Utc::now(),
+            level: self.level,
+            layout: self.layout,
+            chunk_kib: self.chunk_kib,
+            raid_disks: devices.len() as u32,
+            size_bytes: min_size,
+            block_size: 512,
+            component_size: self.component_size,
+            version: self.version,
+            internal_bitmap: self.internal_bitmap,
+        };
+
+        let mut superblocks = Vec::with_capacity(devices.len());
+        for (i, (dev, size)) in devices.iter().zip(&sizes).enumerate() {
+            let device_info =
+                DeviceInfo::new(*size, 512, data_offset, i as u32, None, self.version);
+            let sb = MdpSuperblock1::new(&config, device_info)?;
+            let bitmap = match sb.bitmap_location() {
+                Some(location) => match sb.new_bitmap() {
+                    Some(bitmap) => Some((location, bitmap)),
+                    None => {
+                        return Err(MdError::NoRoomForBitmap {
+                            path: dev.to_string(),
+                        })
+                    }
+                },
+                None => None,
+            };
+            superblocks.push((sb, bitmap));
+        }
+
+        for (dev, (sb, bitmap)) in devices.iter().zip(&superblocks) {
+            let mut file = block::open_exclusive(Path::new(dev))?;
+            // there is only something to wipe when forced. Left behind, another
+            // version's superblock would still be probed
+            zero::wipe_metadata(&mut file, dev)?;
+            file.seek(SeekFrom::Start(sb.device_info.super_offset * 512))?;
+            file.write_all(&sb.as_bytes())?;
+            if let Some((location, bitmap)) = bitmap {
+                file.seek(SeekFrom::Start(*location))?;
+                file.write_all(&bitmap.initial_bytes())?;
+            }
+            file.sync_all()?;
+        }
+        Ok(superblocks.into_iter().map(|(sb, _)| sb).collect())
+    }
+}
+
+/// What `create` would refuse to overwrite on `path`, if anything: md metadata
+/// of any version (even with a bad checksum), or a known filesystem signature
+pub fn existing_signature(path: &str) -> Result<Option<String>> {
+    match Superblock::probe(path) {
+        Ok(sb) => {
+            let version = format!("{}.{}", sb.major_version(), sb.minor_version());
+            return Ok(Some(format!("an md superblock (version {version})")));
+        }
+        Err(MdError::ChecksumMismatch { .. }) => {
+            return Ok(Some("a corrupt md superblock".to_string()));
+        }
+        Err(MdError::Io(err)) => return Err(MdError::Io(err)),
+        Err(_) => {}
+    }
+
+    let mut file = File::open(path)?;
+    for (what, offset, magic) in SIGNATURES {
+        let mut buf = vec![0; magic.len()];
+        file.seek(SeekFrom::Start(*offset))?;
+        match file.read_exact(&mut buf) {
+            Ok(()) if buf == *magic => return Ok(Some(what.to_string())),
+            Ok(()) => {}
+            // shorter than the signature's location
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(None)
+}
+
+/// The system's hostname, mdadm's default homehost
+pub fn hostname() -> Result<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
@@ -0,0 +1,225 @@
+//! Errors returned by the library.
+use crate::ArrayLevel;
+use std::fmt;
+use std::io;
+
+pub type Result<T, E = MdError> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum MdError {
+    /// Reading or writing a device failed
+    Io(io::Error),
+    /// The magic number doesn't match, so this is not md metadata
+    BadMagic {
+        what: &'static str,
+        found: u32,
+    },
+    /// The metadata is in a version this crate doesn't understand
+    UnsupportedVersion {
+        what: &'static str,
+        found: u32,
+    },
+    /// The superblock uses feature bits this crate doesn't understand
+    UnsupportedFeatures(u32),
+    /// The stored checksum doesn't match the contents, the superblock is corrupt
+    ChecksumMismatch {
+        expected: u32,
+        computed: u32,
+    },
+    /// A buffer is too short for the structure being parsed
+    Truncated {
+        what: &'static str,
+    },
+    /// `host:name` does not fit in the 32 bytes of `set_name`
+    NameTooLong {
+        name: String,
+        max: usize,
+    },
+    /// Not enough members for the raid level
+    TooFewDevices {
+        level: ArrayLevel,
+        min: u32,
+        found: u32,
+    },
+    /// The layout is not valid for the raid level or the number of members
+    InvalidLayout {
+        level: ArrayLevel,
+        layout: u32,
+    },
+    /// The chunk size is not a power of two of at least 4KiB, is larger than
+    /// the members, or the level has no chunks
+    InvalidChunkSize {
+        level: ArrayLevel,
+        kib: u32,
+    },
+    /// A requested data offset is not 4KiB aligned, overlaps the metadata or
+    /// leaves no room for data
+    InvalidDataOffset {
+        data_offset: u64,
+        min: u64,
+    },
+    /// The requested component size is larger than the space on the smallest member
+    ComponentSizeTooLarge {
+        size: u64,
+        available: u64,
+    },
+    /// The device is too small to hold a superblock and any data
+    DeviceTooSmall {
+        path: String,
+        size: u64,
+    },
+    /// The device holds md metadata or a filesystem, creating an array over it
+    /// needs to be forced
+    ExistingSignature {
+        path: String,
+        what: String,
+    },
+    /// The device is in use (mounted, part of a running array, ...)
+    DeviceBusy {
+        path: String,
+    },
+    /// There's no space between the superblock and the bad block log for a bitmap
+    NoRoomForBitmap {
+        path: String,
+    },
+    /// None of the superblock locations holds md metadata
+    NoSuperblock {
+        path: String,
+    },
+    NoBitmap {
+        path: String,
+    },
+    NoBadBlockLog {
+        path: String,
+    },
+    BadBlockLogFull {
+        capacity: usize,
+    },
+    /// An md ioctl failed
+    Ioctl {
+        op: &'static str,
+        errno: i32,
+    },
+    NotBlockDevice {
+        path: String,
+    },
+    /// Members passed to assemble belong to different arrays
+    ArrayMismatch {
+        path: String,
+    },
+    /// Members passed to assemble use different superblock versions
+    VersionMismatch {
+        path: String,
+    },
+    /// Too many members are missing for the array to hold all of its data
+    NotEnoughMembers {
+        found: u32,
+        raid_disks: u32,
+    },
+    ReshapeInProgress {
+        path: String,
+        position: u64,
+    },
+}
+
+impl MdError {
+    /// Error for a failed ioctl, taking errno from the last OS error
+    pub(crate) fn ioctl(op: &'static str) -> Self {
+        let errno = io::Error::last_os_error().raw_os_error().unwrap_or(0);
+        MdError::Ioctl { op, errno }
+    }
+}
+
+impl fmt::Display for MdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MdError::Io(err) => write!(f, "{err}"),
+            MdError::BadMagic { what, found } => write!(f, "Invalid {what} magic, got {found:x}"),
+            MdError::UnsupportedVersion { what, found } => {
+                write!(f, "Unsupported {what} version, got {found}")
+            }
+            MdError::UnsupportedFeatures(bits) => {
+                write!(f, "Unsupported feature bits, got {bits:x}")
+            }
+            MdError::ChecksumMismatch { expected, computed } => write!(
+                f,
+                "Superblock checksum mismatch, expected {expected:08x} but computed {computed:08x}"
+            ),
+            MdError::Truncated { what } => write!(f, "Buffer too short for {what}"),
+            MdError::NameTooLong { name, max } => {
+                write!(f, "Array name {name:?} is longer than {max} bytes")
+            }
+            MdError::TooFewDevices { level, min, found } => write!(
+                f,
+                "{level} needs at least {min} devices, got {found}"
+            ),
+            MdError::InvalidLayout { level, layout } => {
+                write!(f, "Layout {layout:#x} is not valid for this {level} array")
+            }
+            MdError::InvalidChunkSize { level, kib } => {
+                write!(f, "Chunk size {kib}KiB is not valid for this {level} array")
+            }
+            MdError::InvalidDataOffset { data_offset, min } => write!(
+                f,
+                "Data offset of {data_offset} sectors is invalid, it must be 4KiB aligned, at least {min} sectors and leave room for data"
+            ),
+            MdError::ComponentSizeTooLarge { size, available } => write!(
+                f,
+                "Component size of {size} sectors is larger than the {available} sectors available"
+            ),
+            MdError::DeviceTooSmall { path, size } => write!(
+                f,
+                "{path} is too small ({size} bytes), the minimum is 10KiB"
+            ),
+            MdError::ExistingSignature { path, what } => write!(
+                f,
+                "{path} appears to contain {what}, use force to overwrite it"
+            ),
+            MdError::DeviceBusy { path } => write!(f, "{path} is busy, is it mounted or in use?"),
+            MdError::NoRoomForBitmap { path } => {
+                write!(f, "No room for an internal bitmap on {path}")
+            }
+            MdError::NoSuperblock { path } => write!(f, "No md superblock found on {path}"),
+            MdError::NoBitmap { path } => write!(f, "{path} has no internal bitmap"),
+            MdError::NoBadBlockLog { path } => write!(f, "{path} has no bad block log"),
+            MdError::BadBlockLogFull { capacity } => {
+                write!(f, "Bad block log is full ({capacity} entries)")
+            }
+            MdError::Ioctl { op, errno } => write!(
+                f,
+                "Can't {op}: {}",
+                io::Error::from_raw_os_error(*errno)
+            ),
+            MdError::NotBlockDevice { path } => write!(f, "{path} is not a block device"),
+            MdError::ArrayMismatch { path } => {
+                write!(f, "{path} does not belong to the same array")
+            }
+            MdError::VersionMismatch { path } => {
+                write!(f, "{path} has a different superblock version")
+            }
+            MdError::NotEnoughMembers { found, raid_disks } => write!(
+                f,
+                "Only {found} of {raid_disks} members are present, not enough to start the array"
+            ),
+            MdError::ReshapeInProgress { path, position } => write!(
+                f,
+                "{path} is part of an array in the middle of a reshape (at sector {position}), refusing to assemble"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MdError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MdError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MdError {
+    fn from(err: io::Error) -> Self {
+        MdError::Io(err)
+    }
+}
@@ -0,0 +1,717 @@
+//! Text rendering of 0.90 and v1.x superblocks, in the same format as `mdadm --examine`.
+use crate::sb090::{DiskDescriptor090, MD_SB_BITMAP_PRESENT, MD_SB_CLEAN};
+use crate::{
+    ioctl, ArrayLayout, ArrayLevel, BitmapSuperblock, FeatureMap, MdpSuperblock090, MdpSuperblock1,
+    Raid10Layout,
+};
+use chrono::{NaiveDateTime, TimeZone};
+use std::fmt;
+
+/// devflags bits, WriteMostly1 and FailFast1 in mdadm
+pub(crate) const DEVFLAG_WRITE_MOSTLY: u8 = 1;
+pub(crate) const DEVFLAG_FAILFAST: u8 = 2;
+
+/// `mdadm --examine` output for a single member, without the leading
+/// `<device>:` line. Built with [`MdpSuperblock1::examine`].
+pub struct Examine<'a, Tz: TimeZone> {
+    sb: &'a MdpSuperblock1,
+    device_size: u64,
+    homehost: Option<&'a str>,
+    bitmap: Option<&'a BitmapSuperblock>,
+    tz: Tz,
+}
+
+impl MdpSuperblock1 {
+    /// Render the superblock like `mdadm --examine`.
+    ///
+    /// `device_size` is the size of the member device in 512b sectors, used for
+    /// the "Unused Space" line. The name is marked as local when it starts with
+    /// `homehost`. mdadm shows times in the local timezone, pass `chrono::Local`
+    /// to match it.
+    pub fn examine<'a, Tz: TimeZone>(
+        &'a self,
+        device_size: u64,
+        homehost: Option<&'a str>,
+        tz: Tz,
+    ) -> Examine<'a, Tz> {
+        Examine {
+            sb: self,
+            device_size,
+            homehost,
+            bitmap: None,
+            tz,
+        }
+    }
+}
+
+impl<'a, Tz: TimeZone> Examine<'a, Tz> {
+    /// The internal bitmap of the device, which mdadm also describes and
+    /// accounts for in "Unused Space"
+    pub fn with_bitmap(mut self, bitmap: &'a BitmapSuperblock) -> Self {
+        self.bitmap = Some(bitmap);
+        self
+    }
+
+    fn ctime(&self, time: NaiveDateTime) -> String
+    where
+        Tz::Offset: fmt::Display,
+    {
+        ctime(&self.tz, time)
+    }
+
+    /// Free sectors before and after the data area, as mdadm's getinfo_super1
+    fn unused_space(&self) -> (u64, u64) {
+        let sb = self.sb;
+        let dev = &sb.device_info;
+        let super_offset = dev.super_offset;
+        let data_size = if sb.array_info.level as i32 <= 0 {
+            dev.data_size
+        } else {
+            sb.array_info.size
+        };
+        let bitmap_offset = sb.array_info.bitmap_offset().unwrap_or(0) as i64;
+        let bblog_offset = if dev.bblog_offset != 0 && dev.bblog_size != 0 {
+            Some(dev.bblog_offset as i32 as i64)
+        } else {
+            None
+        };
+
+        if dev.data_offset < super_offset {
+            // 1.0, metadata after the data
+            let mut end = super_offset as i64;
+            if let Some(bblog_offset) = bblog_offset {
+                end = end.min(super_offset as i64 + bblog_offset);
+            }
+            end = end.min(super_offset as i64 + bitmap_offset);
+            let data_end = (dev.data_offset + data_size) as i64;
+            return (dev.data_offset, (end - data_end).max(0) as u64);
+        }
+
+        // same as the kernel, the superblock takes 72 sectors with all its dev_roles
+        let mut earliest = super_offset + (32 + 4) * 2;
+        if let (Some(bitmap), true) = (self.bitmap, bitmap_offset > 0) {
+            let chunk_sectors = (bitmap.chunksize >> 9).max(1) as u64;
+            let bits = bitmap.sync_size / chunk_sectors;
+            let bytes = bits.div_ceil(8) + BitmapSuperblock::SIZE as u64;
+            let sectors = bytes.next_multiple_of(4096) / 512;
+            earliest = earliest.max((super_offset as i64 + bitmap_offset) as u64 + sectors);
+        }
+        if let Some(bblog_offset) = bblog_offset {
+            let bblog_end = super_offset as i64 + bblog_offset + dev.bblog_size as i64;
+            earliest = earliest.max(bblog_end as u64);
+        }
+        let before = dev.data_offset.saturating_sub(earliest);
+        let after = self.device_size.saturating_sub(data_size + dev.data_offset);
+        (before, after)
+    }
+}
+
+impl<Tz: TimeZone> fmt::Display for Examine<'_, Tz>
+where
+    Tz::Offset: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sb = self.sb;
+        let ai = &sb.array_info;
+        let fb4 = &sb.feature_bit4;
+        let dev = &sb.device_info;
+        let state = &sb.array_state_info;
+        let features = ai.features();
+        let level = ai.level as i32;
+
+        writeln!(f, "          Magic : {:08x}", ai.magic)?;
+        writeln!(f, "        Version : 1.{}", sb.version().minor_version())?;
+        writeln!(f, "    Feature Map : 0x{:x}", ai.feature_map)?;
+        writeln!(
+            f,
+            "     Array UUID : {}",
+            colon_uuid(&ai.uuid().into_bytes())
+        )?;
+        let name = ai.name().unwrap_or_default();
+        write!(f, "           Name : {name}")?;
+        if let Some(host) = self.homehost {
+            if !host.is_empty()
+                && host.len() < 32
+                && name.len() > host.len()
+                && name.starts_with(host)
+                && name.as_bytes()[host.len()] == b':'
+            {
+                write!(f, "  (local to host {host})")?;
+            }
+        }
+        writeln!(f)?;
+        if let (Some(bitmap), true) = (self.bitmap, features.contains(FeatureMap::BITMAP_OFFSET)) {
+            if bitmap.nodes > 0 {
+                writeln!(f, "   Cluster Name : {:<64}", bitmap.cluster_name())?;
+            }
+        }
+        writeln!(f, "  Creation Time : {}", self.ctime(ai.creation()))?;
+        writeln!(f, "     Raid Level : {}", level_name(level))?;
+        writeln!(f, "   Raid Devices : {}", ai.raid_disks)?;
+        writeln!(f)?;
+
+        writeln!(
+            f,
+            " Avail Dev Size : {} sectors{}",
+            dev.data_size,
+            human_size(dev.data_size << 9)
+        )?;
+        if level > 0 {
+            let (data_disks, denominator) = match level {
+                1 => (1, 1),
+                4 | 5 => ((ai.raid_disks as u64).saturating_sub(1), 1),
+                6 => ((ai.raid_disks as u64).saturating_sub(2), 1),
+                10 => {
+                    let copies = Raid10Layout::from(ai.layout).copies();
+                    (ai.raid_disks as u64, copies.max(1) as u64)
+                }
+                _ => (0, 1),
+            };
+            if data_disks != 0 {
+                let array_bytes = (ai.size << 9) * data_disks / denominator;
+                writeln!(
+                    f,
+                    "     Array Size : {} KiB{}",
+                    array_bytes >> 10,
+                    human_size(array_bytes)
+                )?;
+            }
+            if ai.size != dev.data_size {
+                writeln!(
+                    f,
+                    "  Used Dev Size : {} sectors{}",
+                    ai.size,
+                    human_size(ai.size << 9)
+                )?;
+            }
+        }
+        if dev.data_offset != 0 {
+            writeln!(f, "    Data Offset : {} sectors", dev.data_offset)?;
+        }
+        if fb4.new_offset != 0 && features.contains(FeatureMap::NEW_OFFSET) {
+            let new_offset = dev.data_offset as i64 + fb4.new_offset as i32 as i64;
+            writeln!(f, "     New Offset : {new_offset} sectors")?;
+        }
+        writeln!(f, "   Super Offset : {} sectors", dev.super_offset)?;
+        if features.contains(FeatureMap::RECOVERY_OFFSET) {
+            writeln!(f, "Recovery Offset : {} sectors", dev.recovery_offset)?;
+        }
+        if !features.contains(FeatureMap::NEW_OFFSET) {
+            let (before, after) = self.unused_space();
+            writeln!(
+                f,
+                "   Unused Space : before={before} sectors, after={after} sectors"
+            )?;
+        }
+        let array_state = if state.resync_offset == u64::MAX {
+            "clean"
+        } else {
+            "active"
+        };
+        writeln!(f, "          State : {array_state}")?;
+        writeln!(f, "    Device UUID : {}", colon_uuid(&dev.device_uuid))?;
+        writeln!(f)?;
+
+        if let Some(bitmap_offset) = ai.bitmap_offset() {
+            writeln!(
+                f,
+                "Internal Bitmap : {bitmap_offset} sectors from superblock"
+            )?;
+        } else if features.contains(FeatureMap::PPL) {
+            // bitmap_offset is a union with the ppl offset and size
+            let ppl_offset = ai.bitmap_offset as u16 as i16;
+            let ppl_size = (ai.bitmap_offset >> 16) as u16;
+            writeln!(
+                f,
+                "            PPL : {ppl_size} sectors at offset {ppl_offset} sectors from superblock"
+            )?;
+        }
+        if let Some(reshape) = sb.reshape() {
+            let position = reshape.reshape_position;
+            writeln!(
+                f,
+                "  Reshape pos'n : {}{}",
+                position / 2,
+                human_size(position << 9)
+            )?;
+            if reshape.delta_disks != 0 {
+                writeln!(
+                    f,
+                    "  Delta Devices : {} ({}->{})",
+                    reshape.delta_disks,
+                    reshape.old_raid_disks(ai.raid_disks),
+                    ai.raid_disks
+                )?;
+            }
+            if fb4.new_level != ai.level {
+                writeln!(f, "      New Level : {}", level_name(fb4.new_level as i32))?;
+            }
+            if fb4.new_layout != ai.layout {
+                match level {
+                    5 | 6 => writeln!(
+                        f,
+                        "     New Layout : {}",
+                        parity_layout_name(level, fb4.new_layout).unwrap_or("-unknown-")
+                    )?,
+                    10 => writeln!(f, "     New Layout :{}", raid10_layout(fb4.new_layout))?,
+                    _ => {}
+                }
+            }
+            if fb4.new_chunk != ai.chunksize {
+                writeln!(f, "  New Chunksize : {}K", fb4.new_chunk / 2)?;
+            }
+            writeln!(f)?;
+        }
+        if dev.devflags != 0 {
+            write!(f, "          Flags :")?;
+            if dev.devflags & DEVFLAG_WRITE_MOSTLY != 0 {
+                write!(f, " write-mostly")?;
+            }
+            if dev.devflags & DEVFLAG_FAILFAST != 0 {
+                write!(f, " failfast")?;
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f, "    Update Time : {}", self.ctime(state.update_time()))?;
+        if dev.bblog_size != 0 && dev.bblog_offset != 0 {
+            write!(
+                f,
+                "  Bad Block Log : {} entries available at offset {} sectors",
+                dev.bblog_size as u32 * 512 / 8,
+                dev.bblog_offset as i32
+            )?;
+            if features.contains(FeatureMap::BAD_BLOCKS) {
+                write!(f, " - bad blocks present.")?;
+            }
+            writeln!(f)?;
+        }
+        let computed = sb.calculate_sb_csum();
+        if computed == state.sb_csum {
+            writeln!(f, "       Checksum : {:x} - correct", state.sb_csum)?;
+        } else {
+            writeln!(
+                f,
+                "       Checksum : {:x} - expected {:x}",
+                state.sb_csum, computed
+            )?;
+        }
+        writeln!(f, "         Events : {}", state.events)?;
+        writeln!(f)?;
+
+        match level {
+            5 | 6 => writeln!(
+                f,
+                "         Layout : {}",
+                parity_layout_name(level, ai.layout).unwrap_or("-unknown-")
+            )?,
+            10 => writeln!(f, "         Layout :{}", raid10_layout(ai.layout))?,
+            _ => {}
+        }
+        match level {
+            0 | 4 | 5 | 6 | 10 => writeln!(f, "     Chunk Size : {}K", ai.chunksize / 2)?,
+            -1 => writeln!(f, "       Rounding : {}K", ai.chunksize / 2)?,
+            _ => {}
+        }
+        writeln!(f)?;
+
+        // the raw role, mdadm shows reserved values as active devices
+        let role = sb
+            .dev_roles
+            .get(dev.dev_number as usize)
+            .copied()
+            .unwrap_or(u16::MAX);
+        match role {
+            0xfffe.. => writeln!(f, "   Device Role : spare")?,
+            0xfffd => writeln!(f, "   Device Role : Journal")?,
+            _ if features.contains(FeatureMap::REPLACEMENT) => {
+                writeln!(f, "   Device Role : Replacement device {role}")?
+            }
+            _ => writeln!(f, "   Device Role : Active device {role}")?,
+        }
+
+        let array_state = sb.array_state();
+        write!(f, "   Array State : {array_state}")?;
+        if array_state.contains('?') {
+            write!(
+                f,
+                "\n   WARNING Array State is inconsistent - each number should appear only once"
+            )?;
+            for (i, &role) in sb.dev_roles.iter().enumerate() {
+                if role >= 0xfffe {
+                    write!(f, " {i}:-")?;
+                } else {
+                    write!(f, " {i}:{role}")?;
+                }
+            }
+        }
+        writeln!(f, " ('A' == active, '.' == missing, 'R' == replacing)")
+    }
+}
+
+/// `mdadm --examine` output for a 0.90 member, without the leading
+/// `<device>:` line. Built with [`MdpSuperblock090::examine`].
+pub struct Examine090<'a, Tz: TimeZone> {
+    sb: &'a MdpSuperblock090,
+    tz: Tz,
+}
+
+impl MdpSuperblock090 {
+    /// Render the superblock like `mdadm --examine`. mdadm shows times in the
+    /// local timezone, pass `chrono::Local` to match it.
+    ///
+    /// mdadm also marks the uuid as local to the host when it was derived from
+    /// the hostname, and names the device of each descriptor. Neither is shown
+    pub fn examine<Tz: TimeZone>(&self, tz: Tz) -> Examine090<'_, Tz> {
+        Examine090 { sb: self, tz }
+    }
+}
+
+impl<Tz: TimeZone> fmt::Display for Examine090<'_, Tz>
+where
+    Tz::Offset: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sb = self.sb;
+        let level = sb.level as i32;
+
+        writeln!(
+            f,
+            "          Magic : {:08x}",
+            MdpSuperblock090::SUPERBLOCK_MAGIC
+        )?;
+        writeln!(
+            f,
+            "        Version : {}.{:02}.{:02}",
+            sb.major_version, sb.minor_version, sb.patch_version
+        )?;
+        writeln!(
+            f,
+            "           UUID : {}",
+            colon_uuid(&sb.uuid().into_bytes())
+        )?;
+        if sb.not_persistent != 0 {
+            writeln!(f, "           Eedk : not persistent")?;
+        }
+        writeln!(f, "  Creation Time : {}", ctime(&self.tz, sb.creation()))?;
+        writeln!(f, "     Raid Level : {}", level_name(level))?;
+        if level > 0 {
+            // size is in KiB
+            let size = sb.size as u64;
+            writeln!(f, "  Used Dev Size : {}{}", size, human_size(size << 10))?;
+            let raid_disks = sb.raid_disks as u64;
+            let (data_disks, denominator) = match level {
+                1 => (1, 1),
+                4 | 5 => (raid_disks.saturating_sub(1), 1),
+                6 => (raid_disks.saturating_sub(2), 1),
+                10 => {
+                    let copies = Raid10Layout::from(sb.layout).copies();
+                    (raid_disks, copies.max(1) as u64)
+                }
+                _ => (0, 1),
+            };
+            if data_disks != 0 {
+                let array_bytes = (size << 10) * data_disks / denominator;
+                writeln!(
+                    f,
+                    "     Array Size : {}{}",
+                    array_bytes >> 10,
+                    human_size(array_bytes)
+                )?;
+            }
+        }
+        writeln!(f, "   Raid Devices : {}", sb.raid_disks)?;
+        writeln!(f, "  Total Devices : {}", sb.nr_disks)?;
+        writeln!(f, "Preferred Minor : {}", sb.md_minor)?;
+        writeln!(f)?;
+
+        let mut delta_extra = 0;
+        if let Some(reshape) = sb.reshape() {
+            let position = reshape.reshape_position;
+            writeln!(
+                f,
+                "  Reshape pos'n : {}{}",
+                position / 2,
+                human_size(position << 9)
+            )?;
+            if reshape.delta_disks != 0 {
+                writeln!(
+                    f,
+                    "  Delta Devices : {} ({}->{})",
+                    reshape.delta_disks,
+                    reshape.old_raid_disks(sb.raid_disks),
+                    sb.raid_disks
+                )?;
+                if reshape.delta_disks < 0 {
+                    delta_extra = -reshape.delta_disks as u32;
+                }
+            }
+            if sb.new_level != sb.level {
+                writeln!(f, "      New Level : {}", level_name(sb.new_level as i32))?;
+            }
+            if sb.new_layout != sb.layout {
+                match level {
+                    5 | 6 => writeln!(
+                        f,
+                        "     New Layout : {}",
+                        parity_layout_name(level, sb.new_layout).unwrap_or("-unknown-")
+                    )?,
+                    10 => writeln!(f, "     New Layout :{}", raid10_layout(sb.new_layout))?,
+                    _ => {}
+                }
+            }
+            // in bytes, unlike v1.x
+            if sb.new_chunk != sb.chunk_size {
+                writeln!(f, "  New Chunksize : {}", sb.new_chunk)?;
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f, "    Update Time : {}", ctime(&self.tz, sb.update_time()))?;
+        let clean = sb.state & (1 << MD_SB_CLEAN) != 0;
+        writeln!(
+            f,
+            "          State : {}",
+            if clean { "clean" } else { "active" }
+        )?;
+        if sb.state & (1 << MD_SB_BITMAP_PRESENT) != 0 {
+            writeln!(f, "Internal Bitmap : present")?;
+        }
+        writeln!(f, " Active Devices : {}", sb.active_disks)?;
+        writeln!(f, "Working Devices : {}", sb.working_disks)?;
+        writeln!(f, " Failed Devices : {}", sb.failed_disks)?;
+        writeln!(f, "  Spare Devices : {}", sb.spare_disks)?;
+        let computed = sb.calculate_sb_csum();
+        if computed == sb.sb_csum {
+            writeln!(f, "       Checksum : {:x} - correct", sb.sb_csum)?;
+        } else {
+            writeln!(
+                f,
+                "       Checksum : {:x} - expected {:x}",
+                sb.sb_csum, computed
+            )?;
+        }
+        writeln!(f, "         Events : {}", sb.events)?;
+        writeln!(f)?;
+
+        match level {
+            5 | 6 => writeln!(
+                f,
+                "         Layout : {}",
+                parity_layout_name(level, sb.layout).unwrap_or("-unknown-")
+            )?,
+            10 => writeln!(f, "         Layout :{}", raid10_layout(sb.layout))?,
+            _ => {}
+        }
+        // in bytes, unlike v1.x
+        match level {
+            0 | 4 | 5 | 6 | 10 => writeln!(f, "     Chunk Size : {}K", sb.chunk_size / 1024)?,
+            -1 => writeln!(f, "       Rounding : {}K", sb.chunk_size / 1024)?,
+            _ => {}
+        }
+        writeln!(f)?;
+
+        writeln!(f, "      Number   Major   Minor   RaidDevice State")?;
+        write_disk(f, "this", &sb.this_disk)?;
+        writeln!(f)?;
+        let rows = sb.raid_disks as usize + delta_extra as usize + sb.spare_disks as usize;
+        for (i, disk) in sb.disks.iter().enumerate().take(rows) {
+            write_disk(f, &format!("{i:4}"), disk)?;
+        }
+        Ok(())
+    }
+}
+
+/// One row of the 0.90 descriptor table
+fn write_disk(f: &mut fmt::Formatter<'_>, label: &str, disk: &DiskDescriptor090) -> fmt::Result {
+    write!(
+        f,
+        "{label:>4} {:5}   {:5}    {:5}    {:5}     ",
+        disk.number as i32, disk.major as i32, disk.minor as i32, disk.raid_disk as i32
+    )?;
+    let flags = (1 << ioctl::MD_DISK_WRITEMOSTLY) | (1 << ioctl::MD_DISK_FAILFAST);
+    let state = disk.state & !flags;
+    for (bit, name) in [
+        (ioctl::MD_DISK_FAULTY, "faulty"),
+        (ioctl::MD_DISK_ACTIVE, "active"),
+        (ioctl::MD_DISK_SYNC, "sync"),
+        (ioctl::MD_DISK_REMOVED, "removed"),
+        (ioctl::MD_DISK_WRITEMOSTLY, "write-mostly"),
+        (ioctl::MD_DISK_FAILFAST, "failfast"),
+    ] {
+        if disk.state & (1 << bit) != 0 {
+            write!(f, " {name}")?;
+        }
+    }
+    if state == 0 {
+        write!(f, " spare")?;
+    }
+    writeln!(f)
+}
+
+/// Like ctime(3), without the trailing newline
+fn ctime<Tz: TimeZone>(tz: &Tz, time: NaiveDateTime) -> String
+where
+    Tz::Offset: fmt::Display,
+{
+    tz.from_utc_datetime(&time)
+        .format("%a %b %e %H:%M:%S %Y")
+        .to_string()
+}
+
+/// mdadm's name for a level, from its pers table
+fn level_name(level: i32) -> String {
+    ArrayLevel::from_raw(level).map_or("-unknown-".to_string(), |l| l.to_string())
+}
+
+/// 16 bytes as 4 colon separated groups, the way mdadm prints uuids
+fn colon_uuid(bytes: &[u8; 16]) -> String {
+    bytes
+        .chunks(4)
+        .map(|chunk| chunk.iter().map(|b| format!("{b:02x}")).collect::<String>())
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// mdadm's human_size: nothing below ~5MB, MiB/MB below 2GiB, GiB/GB above.
+/// Values are rounded to hundredths
+pub fn human_size(bytes: u64) -> String {
+    let bytes = bytes as i128;
+    if bytes < 5000 * 1024 {
+        String::new()
+    } else if bytes < 2 * 1024 * 1024 * 1024 {
+        let c_mib = (bytes * 200 / (1 << 20) + 1) / 2;
+        let c_mb = (bytes / (1_000_000 / 200) + 1) / 2;
+        format!(
+            " ({}.{:02} MiB {}.{:02} MB)",
+            c_mib / 100,
+            c_mib % 100,
+            c_mb / 100,
+            c_mb % 100
+        )
+    } else {
+        let c_gib = (bytes * 200 / (1 << 30) + 1) / 2;
+        let c_gb = (bytes / (1_000_000_000 / 200) + 1) / 2;
+        format!(
+            " ({}.{:02} GiB {}.{:02} GB)",
+            c_gib / 100,
+            c_gib % 100,
+            c_gb / 100,
+            c_gb % 100
+        )
+    }
+}
+
+/// Names from mdadm's r5layout and r6layout tables, the -6 layouts only have
+/// names for raid6
+fn parity_layout_name(level: i32, layout: u32) -> Option<&'static str> {
+    let layout = ArrayLayout::from_raw(layout)?;
+    if level != 6 && layout as u32 >= ArrayLayout::LeftAsymmetric6 as u32 {
+        return None;
+    }
+    Some(layout.name())
+}
+
+/// mdadm's print_r10_layout, including the leading space
+fn raid10_layout(layout: u32) -> String {
+    let layout = Raid10Layout::from(layout);
+    let mut ret = String::new();
+    let mut sep = "";
+    if layout.near != 1 {
+        ret += &format!("{sep} near={}", layout.near);
+        sep = ",";
+    }
+    if layout.far != 1 {
+        let kind = if layout.offset { "offset" } else { "far" };
+        ret += &format!("{sep} {kind}={}", layout.far);
+    }
+    if layout.copies() == 1 {
+        ret += "NO REDUNDANCY";
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ArrayConfig, ArrayLevel, DeviceInfo, MdError};
+    use chrono::Utc;
+
+    /// raid1 member of `device_mib`, in an array whose smallest member is 10MiB
+    fn raid1_member(device_mib: u64, component_size: Option<u64>) -> crate::Result<MdpSuperblock1> {
+        let config = ArrayConfig {
+            component_size,
+            ..crate::tests::config(ArrayLevel::Raid1, 2)
+        };
+        let device_info =
+            DeviceInfo::new(device_mib * 1024 * 1024, 512, 2048, 1, None, config.version);
+        MdpSuperblock1::new(&config, device_info)
+    }
+
+    #[test]
+    fn test_mixed_member_sizes() {
+        let out = raid1_member(10, None)
+            .unwrap()
+            .examine(20480, None, Utc)
+            .to_string();
+        assert!(!out.contains("Used Dev Size"));
+        assert!(out.contains("   Unused Space : before=1968 sectors, after=0 sectors\n"));
+
+        // a 20MiB member only uses what the 10MiB one has
+        let out = raid1_member(20, None)
+            .unwrap()
+            .examine(40960, None, Utc)
+            .to_string();
+        assert!(out.contains(" Avail Dev Size : 38912 sectors (19.00 MiB 19.92 MB)\n"));
+        assert!(out.contains("  Used Dev Size : 18432 sectors (9.00 MiB 9.44 MB)\n"));
+        assert!(out.contains("   Unused Space : before=1968 sectors, after=20480 sectors\n"));
+
+        // an explicit smaller size leaves space after the data on every member
+        let out = raid1_member(10, Some(16384))
+            .unwrap()
+            .examine(20480, None, Utc)
+            .to_string();
+        assert!(out.contains("  Used Dev Size : 16384 sectors (8.00 MiB 8.39 MB)\n"));
+        assert!(out.contains("   Unused Space : before=1968 sectors, after=2048 sectors\n"));
+
+        assert!(matches!(
+            raid1_member(20, Some(18433)),
+            Err(MdError::ComponentSizeTooLarge {
+                size: 18433,
+                available: 18432
+            })
+        ));
+    }
+
+    #[test]
+    fn test_zeroed_raid_disks() {
+        let config = crate::tests::config(ArrayLevel::Raid6, 4);
+        let mut sb = crate::tests::member(&config, 2048, 0).unwrap();
+        sb.array_info.raid_disks = 0;
+        let out = sb.examine(20480, None, Utc).to_string();
+        assert!(out.contains("   Raid Devices : 0\n"));
+        assert!(!out.contains("Array Size"));
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(4096), "");
+        assert_eq!(human_size(9 * 1024 * 1024), " (9.00 MiB 9.44 MB)");
+        assert_eq!(
+            human_size(1000 * 1024 * 1024 * 1024),
+            " (1000.00 GiB 1073.74 GB)"
+        );
+    }
+
+    #[test]
+    fn test_layout_names() {
+        assert_eq!(parity_layout_name(5, 2), Some("left-symmetric"));
+        assert_eq!(parity_layout_name(5, 18), None);
+        assert_eq!(parity_layout_name(6, 18), Some("left-symmetric-6"));
+        assert_eq!(raid10_layout(0x102), " near=2");
+        assert_eq!(raid10_layout(0x201), " far=2");
+        assert_eq!(raid10_layout(0x10201), " offset=2");
+        assert_eq!(raid10_layout(0x101), "NO REDUNDANCY");
+        assert_eq!(raid10_layout(0x202), " near=2, far=2");
+    }
+}
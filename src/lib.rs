@@ -1,31 +1,63 @@
-use arrayref::array_ref;
+use arrayref::{array_ref, array_refs, mut_array_refs};
 use chrono::{DateTime, Utc};
 use std::convert::From;
 use std::fs::File;
-use std::io::{self, Error, Read, Seek, SeekFrom};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::string::FromUtf8Error;
 use uuid::Uuid;
 
+pub mod assemble;
+pub mod bblog;
+pub mod bitmap;
+pub mod block;
+pub mod control;
+pub mod create;
+pub mod error;
+pub mod examine;
 pub mod ioctl;
+pub mod sb090;
+#[cfg(feature = "serde")]
+mod serde_fields;
+pub mod zero;
 
-#[repr(C, packed)]
-#[derive(Debug, Clone, Copy)]
+pub use bblog::BadBlockLog;
+pub use bitmap::BitmapSuperblock;
+pub use error::{MdError, Result};
+pub use sb090::MdpSuperblock090;
+
+/// Constant array information, bytes 0..100 of the superblock. All fields
+/// are stored little-endian on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ArrayInfo {
     pub magic: u32,
     pub major_version: u32,
+    #[cfg_attr(feature = "serde", serde(with = "serde_fields::feature_map"))]
     pub feature_map: u32,
+    #[cfg_attr(feature = "serde", serde(skip))]
     _pad0: u32,
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "uuid", with = "serde_fields::uuid_bytes")
+    )]
     set_uuid: [u8; 16],
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "name", with = "serde_fields::set_name")
+    )]
     set_name: [u8; 32],
-    ctime: u64,          // /* lo 40 bits are seconds, top 24 are microseconds or 0*/
-    pub level: u32,      /* -4 (multipath), -1 (linear), 0,1,4,5 */
+    #[cfg_attr(feature = "serde", serde(with = "serde_fields::timestamp"))]
+    ctime: u64, // /* lo 40 bits are seconds, top 24 are microseconds or 0*/
+    #[cfg_attr(feature = "serde", serde(with = "serde_fields::level"))]
+    pub level: u32, /* -4 (multipath), -1 (linear), 0,1,4,5 */
     pub layout: u32,     /* used for raid5, raid6, raid10, and raid0 */
     pub size: u64,       // in 512b sectors
     pub chunksize: u32,  // in 512b sectors
     pub raid_disks: u32, // count
-    // Union of offset + size for MD_FEATURE_PPL
-    // TODO
-    opaque_union_bitmap_offset_ppl: u32,
+    // signed sector offset of the bitmap from the superblock when
+    // FeatureMap::BITMAP_OFFSET is set; holds the offset + size of the
+    // partial parity log instead with FeatureMap::PPL
+    bitmap_offset: u32,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -46,12 +78,157 @@ impl From<ArrayLevel> for u32 {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl ArrayLevel {
+    /// Smallest chunk size accepted at creation, one page
+    pub const MIN_CHUNK_KIB: u32 = 4;
+
+    /// Chunk size in 512b sectors for a requested size in KiB, 512KiB by
+    /// default for striped levels and 0 for the others
+    pub fn chunk_sectors(&self, chunk_kib: Option<u32>) -> Result<u32> {
+        match chunk_kib {
+            None if self.is_striped() => Ok(1024),
+            None => Ok(0),
+            // linear uses the chunk size to round the member sizes
+            Some(kib) if self.is_striped() || *self == ArrayLevel::Linear => {
+                if !kib.is_power_of_two() || kib < ArrayLevel::MIN_CHUNK_KIB {
+                    return Err(MdError::InvalidChunkSize { level: *self, kib });
+                }
+                // chunksize is stored in sectors, 2TiB doesn't fit
+                kib.checked_mul(2)
+                    .ok_or(MdError::InvalidChunkSize { level: *self, kib })
+            }
+            Some(kib) => Err(MdError::InvalidChunkSize { level: *self, kib }),
+        }
+    }
+
+    /// Whether data is split in chunks across the members. The other levels
+    /// have no chunk size
+    pub fn is_striped(&self) -> bool {
+        matches!(
+            self,
+            ArrayLevel::Raid0
+                | ArrayLevel::Raid4
+                | ArrayLevel::Raid5
+                | ArrayLevel::Raid6
+                | ArrayLevel::Raid10
+        )
+    }
+
+    /// Fewest members an array of this level can be created with, same as mdadm
+    pub fn min_raid_disks(&self) -> u32 {
+        match self {
+            ArrayLevel::Linear | ArrayLevel::Raid0 | ArrayLevel::Raid1 | ArrayLevel::Multipath => 1,
+            ArrayLevel::Raid4 | ArrayLevel::Raid5 | ArrayLevel::Raid10 => 2,
+            ArrayLevel::Raid6 => 4,
+        }
+    }
+
+    /// Whether an array with only the `present` slots (indexed by slot, one
+    /// per raid disk) still has a copy of all its data, same as mdadm's enough()
+    pub fn can_run(&self, layout: u32, present: &[bool]) -> bool {
+        let raid_disks = present.len();
+        let found = present.iter().filter(|&&p| p).count();
+        match self {
+            ArrayLevel::Linear | ArrayLevel::Raid0 => found == raid_disks,
+            ArrayLevel::Raid1 | ArrayLevel::Multipath => found >= 1,
+            ArrayLevel::Raid4 | ArrayLevel::Raid5 => found + 1 >= raid_disks,
+            ArrayLevel::Raid6 => found + 2 >= raid_disks,
+            ArrayLevel::Raid10 => {
+                // every run of `copies` slots, starting at each multiple of the
+                // near copies, holds one copy of the same chunks
+                let layout = Raid10Layout::from(layout);
+                let copies = layout.copies() as usize;
+                let near = (layout.near as usize).max(1);
+                if raid_disks == 0 {
+                    return false;
+                }
+                let mut first = 0;
+                loop {
+                    if !(0..copies).any(|i| present[(first + i) % raid_disks]) {
+                        return false;
+                    }
+                    first = (first + near) % raid_disks;
+                    if first == 0 {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Layout used when none is given: left-symmetric for parity levels,
+    /// two near copies for raid10
+    pub fn default_layout(&self) -> u32 {
+        match self {
+            ArrayLevel::Raid5 | ArrayLevel::Raid6 => ArrayLayout::LeftSymmetric.into(),
+            ArrayLevel::Raid10 => Raid10Layout::near(2).into(),
+            _ => 0,
+        }
+    }
+
+    /// Level as stored in superblocks and passed to the md ioctls
+    pub fn from_raw(level: i32) -> Option<Self> {
+        Some(match level {
+            -4 => ArrayLevel::Multipath,
+            -1 => ArrayLevel::Linear,
+            0 => ArrayLevel::Raid0,
+            1 => ArrayLevel::Raid1,
+            4 => ArrayLevel::Raid4,
+            5 => ArrayLevel::Raid5,
+            6 => ArrayLevel::Raid6,
+            10 => ArrayLevel::Raid10,
+            _ => return None,
+        })
+    }
+}
+
+/// Accepts the names printed by Display, and bare numbers ("5", "raid5", "linear")
+impl std::str::FromStr for ArrayLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let raw = match s.strip_prefix("raid").unwrap_or(s) {
+            "linear" => -1,
+            "multipath" | "mp" => -4,
+            n => n.parse().map_err(|_| format!("unknown raid level {s}"))?,
+        };
+        ArrayLevel::from_raw(raw).ok_or_else(|| format!("unknown raid level {s}"))
+    }
+}
+
+/// Same names as mdadm's "Raid Level"
+impl std::fmt::Display for ArrayLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArrayLevel::Linear => write!(f, "linear"),
+            ArrayLevel::Multipath => write!(f, "multipath"),
+            level => write!(f, "raid{}", *level as i32),
+        }
+    }
+}
+
+/// Parity placement for raid4/5/6, ALGORITHM_* in the kernel's raid5.h
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ArrayLayout {
     LeftAsymmetric = 0,
     RightAsymmetric = 1,
     LeftSymmetric = 2,
     RightSymmetric = 3,
+    /// Parity on the first device, parity_0
+    ParityFirst = 4,
+    /// Parity on the last device, parity_n. Same as raid4
+    ParityLast = 5,
+    /// DDF layouts, raid6 only
+    DdfZeroRestart = 8,
+    DdfNRestart = 9,
+    DdfNContinue = 10,
+    /// raid5 layouts with the Q syndrome on the last device, used while
+    /// converting raid5 to raid6
+    LeftAsymmetric6 = 16,
+    RightAsymmetric6 = 17,
+    LeftSymmetric6 = 18,
+    RightSymmetric6 = 19,
+    ParityFirst6 = 20,
 }
 
 impl From<ArrayLayout> for u32 {
@@ -60,81 +237,543 @@ impl From<ArrayLayout> for u32 {
     }
 }
 
+impl ArrayLayout {
+    const ALL: [ArrayLayout; 14] = [
+        ArrayLayout::LeftAsymmetric,
+        ArrayLayout::RightAsymmetric,
+        ArrayLayout::LeftSymmetric,
+        ArrayLayout::RightSymmetric,
+        ArrayLayout::ParityFirst,
+        ArrayLayout::ParityLast,
+        ArrayLayout::DdfZeroRestart,
+        ArrayLayout::DdfNRestart,
+        ArrayLayout::DdfNContinue,
+        ArrayLayout::LeftAsymmetric6,
+        ArrayLayout::RightAsymmetric6,
+        ArrayLayout::LeftSymmetric6,
+        ArrayLayout::RightSymmetric6,
+        ArrayLayout::ParityFirst6,
+    ];
+
+    /// Layout as stored in `ArrayInfo::layout`
+    pub fn from_raw(layout: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|l| *l as u32 == layout)
+    }
+
+    /// Name used by mdadm for `--layout` and in `--examine`
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArrayLayout::LeftAsymmetric => "left-asymmetric",
+            ArrayLayout::RightAsymmetric => "right-asymmetric",
+            ArrayLayout::LeftSymmetric => "left-symmetric",
+            ArrayLayout::RightSymmetric => "right-symmetric",
+            ArrayLayout::ParityFirst => "parity-first",
+            ArrayLayout::ParityLast => "parity-last",
+            ArrayLayout::DdfZeroRestart => "ddf-zero-restart",
+            ArrayLayout::DdfNRestart => "ddf-N-restart",
+            ArrayLayout::DdfNContinue => "ddf-N-continue",
+            ArrayLayout::LeftAsymmetric6 => "left-asymmetric-6",
+            ArrayLayout::RightAsymmetric6 => "right-asymmetric-6",
+            ArrayLayout::LeftSymmetric6 => "left-symmetric-6",
+            ArrayLayout::RightSymmetric6 => "right-symmetric-6",
+            ArrayLayout::ParityFirst6 => "parity-first-6",
+        }
+    }
+
+    /// Whether the kernel accepts this layout for `level`. raid4 always keeps
+    /// parity on the last device, mdadm stores 0 for it
+    pub fn is_valid_for(&self, level: ArrayLevel) -> bool {
+        let raw = *self as u32;
+        match level {
+            ArrayLevel::Raid4 => {
+                matches!(self, ArrayLayout::LeftAsymmetric | ArrayLayout::ParityLast)
+            }
+            ArrayLevel::Raid5 => raw <= 5,
+            ArrayLevel::Raid6 => true,
+            _ => false,
+        }
+    }
+}
+
+/// Accepts mdadm's names, and the la/ra/ls/rs shorthands
+impl std::str::FromStr for ArrayLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let name = match s {
+            "la" => "left-asymmetric",
+            "ra" => "right-asymmetric",
+            "ls" => "left-symmetric",
+            "rs" => "right-symmetric",
+            name => name,
+        };
+        Self::ALL
+            .into_iter()
+            .find(|l| l.name() == name)
+            .ok_or_else(|| format!("unknown parity layout {s}"))
+    }
+}
+
+impl std::fmt::Display for ArrayLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Placement of the copies in a raid10 array, encoded in `ArrayInfo::layout`
+/// as near copies in bits 0..8, far copies in bits 8..16 and the offset flag
+/// in bit 16
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Raid10Layout {
+    /// Copies of a chunk on consecutive devices, within the same stripe
+    pub near: u8,
+    /// Copies of a chunk in other parts of the devices
+    pub far: u8,
+    /// The far copies are in the next stripe instead of far away on the device
+    pub offset: bool,
+}
+
+impl Raid10Layout {
+    const OFFSET: u32 = 1 << 16;
+
+    /// `n<copies>`, mdadm's default with 2 copies
+    pub fn near(copies: u8) -> Self {
+        Raid10Layout {
+            near: copies,
+            far: 1,
+            offset: false,
+        }
+    }
+    /// `f<copies>`
+    pub fn far(copies: u8) -> Self {
+        Raid10Layout {
+            near: 1,
+            far: copies,
+            offset: false,
+        }
+    }
+    /// `o<copies>`
+    pub fn offset(copies: u8) -> Self {
+        Raid10Layout {
+            near: 1,
+            far: copies,
+            offset: true,
+        }
+    }
+    /// Number of copies of each chunk
+    pub fn copies(&self) -> u32 {
+        self.near as u32 * self.far as u32
+    }
+}
+
+impl From<u32> for Raid10Layout {
+    fn from(layout: u32) -> Self {
+        Raid10Layout {
+            near: layout as u8,
+            far: (layout >> 8) as u8,
+            offset: layout & Self::OFFSET != 0,
+        }
+    }
+}
+
+impl From<Raid10Layout> for u32 {
+    fn from(layout: Raid10Layout) -> Self {
+        let offset = if layout.offset {
+            Raid10Layout::OFFSET
+        } else {
+            0
+        };
+        layout.near as u32 | (layout.far as u32) << 8 | offset
+    }
+}
+
+/// mdadm's `--layout` syntax: n2, f2, o2. Combined layouts are written as
+/// both parts, like n2f2
+impl std::str::FromStr for Raid10Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let err =
+            || format!("invalid raid10 layout {s}, expected n<copies>, f<copies> or o<copies>");
+        let mut layout = Raid10Layout::near(1);
+        let mut rest = s;
+        while let Some(kind) = rest.chars().next() {
+            if !kind.is_ascii() {
+                return Err(err());
+            }
+            let digits = rest[1..]
+                .find(|c: char| !c.is_ascii_digit())
+                .map_or(rest.len(), |i| i + 1);
+            let copies: u8 = rest[1..digits].parse().map_err(|_| err())?;
+            match kind {
+                'n' => layout.near = copies,
+                'f' => layout.far = copies,
+                'o' => {
+                    layout.far = copies;
+                    layout.offset = true;
+                }
+                _ => return Err(err()),
+            }
+            rest = &rest[digits..];
+        }
+        if s.is_empty() || layout.near == 0 || layout.far == 0 {
+            return Err(err());
+        }
+        Ok(layout)
+    }
+}
+
+impl std::fmt::Display for Raid10Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.near != 1 || self.far == 1 {
+            write!(f, "n{}", self.near)?;
+        }
+        if self.far != 1 {
+            let kind = if self.offset { 'o' } else { 'f' };
+            write!(f, "{kind}{}", self.far)?;
+        }
+        Ok(())
+    }
+}
+
+/// Role of a member device, stored per dev_number in `MdpSuperblock1::dev_roles`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum DeviceRole {
+    /// Slot in the array
+    Active(u16),
+    Spare,
+    Faulty,
+    Journal,
+}
+
+impl From<u16> for DeviceRole {
+    fn from(role: u16) -> Self {
+        match role as u32 {
+            ioctl::MD_DISK_ROLE_SPARE => DeviceRole::Spare,
+            ioctl::MD_DISK_ROLE_FAULTY => DeviceRole::Faulty,
+            ioctl::MD_DISK_ROLE_JOURNAL => DeviceRole::Journal,
+            n if n <= ioctl::MD_DISK_ROLE_MAX => DeviceRole::Active(role),
+            // reserved values, mdadm shows these as spares too
+            _ => DeviceRole::Spare,
+        }
+    }
+}
+
+impl From<DeviceRole> for u16 {
+    fn from(role: DeviceRole) -> Self {
+        match role {
+            DeviceRole::Active(n) => n,
+            DeviceRole::Spare => ioctl::MD_DISK_ROLE_SPARE as u16,
+            DeviceRole::Faulty => ioctl::MD_DISK_ROLE_FAULTY as u16,
+            DeviceRole::Journal => ioctl::MD_DISK_ROLE_JOURNAL as u16,
+        }
+    }
+}
+
+/// Inverse of `instant_to_arrayinfo_format`
+fn sb_time(time: u64) -> chrono::NaiveDateTime {
+    let seconds = time & 0xFF_FFFF_FFFF; // bottom 40b
+    let micros = (time >> 40) as u32; // top 24b
+    DateTime::from_timestamp(seconds as i64, micros.saturating_mul(1000))
+        .unwrap_or_default()
+        .naive_local()
+}
+
 fn instant_to_arrayinfo_format(when: DateTime<Utc>) -> u64 {
     let seconds = when.timestamp() as u64;
     let microseconds = when.timestamp_subsec_micros() as u64;
 
     // Combine seconds (lower 40 bits) and microseconds (upper 24 bits)
-    (seconds & 0xFFFFFFFFFF) | ((microseconds as u64) << 40)
+    (seconds & 0xFFFFFFFFFF) | (microseconds << 40)
 }
 
 fn str_to_bytes(s: &str) -> [u8; 32] {
     let mut bytes = [0u8; 32];
+    if let Some(slice) = bytes.get_mut(..s.len()) {
+        slice.copy_from_slice(s.as_bytes());
+    }
     bytes
-        .get_mut(..s.len())
-        .map(|slice| slice.copy_from_slice(s.as_bytes()));
-    bytes
+}
+
+/// Minor version of a v1.x superblock, which determines where on the
+/// member device the superblock is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuperblockVersion {
+    /// At the end of the device, 8-12KiB before the end
+    V1_0,
+    /// At the start of the device
+    V1_1,
+    /// 4KiB after the start of the device
+    V1_2,
+}
+
+impl SuperblockVersion {
+    /// Order in which locations are probed when the version is not known
+    pub const ALL: [SuperblockVersion; 3] = [
+        SuperblockVersion::V1_2,
+        SuperblockVersion::V1_1,
+        SuperblockVersion::V1_0,
+    ];
+
+    /// Bad block log size reserved next to the superblock, in 512b sectors
+    const BBLOG_SECTORS: u64 = 8;
+
+    pub fn minor_version(&self) -> u32 {
+        match self {
+            SuperblockVersion::V1_0 => 0,
+            SuperblockVersion::V1_1 => 1,
+            SuperblockVersion::V1_2 => 2,
+        }
+    }
+
+    /// Location of the superblock, in 512b sectors from the start of the device.
+    /// Returns None if the device is too small to hold a superblock.
+    pub fn super_offset(&self, device_size_sectors: u64) -> Option<u64> {
+        match self {
+            // 8KiB from the end, rounded down to a 4KiB boundary
+            SuperblockVersion::V1_0 => device_size_sectors
+                .checked_sub(8 * 2)
+                .map(|o| o & !(4 * 2 - 1)),
+            SuperblockVersion::V1_1 => Some(0),
+            SuperblockVersion::V1_2 => Some(8),
+        }
+    }
+
+    /// Same heuristic as mdadm: the version is implied by the superblock location
+    pub fn from_super_offset(super_offset: u64) -> Self {
+        match super_offset {
+            0..=4 => SuperblockVersion::V1_1,
+            5..=8 => SuperblockVersion::V1_2,
+            _ => SuperblockVersion::V1_0,
+        }
+    }
+
+    /// End of the superblock, bitmap and bad block log reservations that come
+    /// before the data, in 512b sectors. 1.0 keeps all of them at the end.
+    fn metadata_end(&self, device_size_sectors: u64) -> u64 {
+        match self {
+            SuperblockVersion::V1_0 => 0,
+            SuperblockVersion::V1_1 | SuperblockVersion::V1_2 => {
+                self.super_offset(device_size_sectors).unwrap_or(0)
+                    + self.bblog_offset(device_size_sectors) as u64
+                    + Self::BBLOG_SECTORS
+            }
+        }
+    }
+
+    /// Start of the data area, in 512b sectors, shared by members of the
+    /// given sizes.
+    ///
+    /// Same as mdadm: 1.0 keeps the metadata at the end, so data starts at the
+    /// beginning of the device. 1.1 and 1.2 leave headroom for reshapes, 0.1%
+    /// of the smallest member capped at 128MiB but at least four chunks, and at
+    /// least room for the metadata; rounded up to 1MiB.
+    ///
+    /// `requested` overrides the computed offset, like mdadm's `--data-offset`.
+    /// It must be 4KiB aligned, after the metadata and before the end of every
+    /// member. Larger members reserve more space for a bitmap, which pushes
+    /// their bad block log further in.
+    pub fn data_offset(
+        &self,
+        member_sizes_sectors: &[u64],
+        chunk_sectors: u32,
+        requested: Option<u64>,
+    ) -> Result<u64> {
+        let min = member_sizes_sectors
+            .iter()
+            .map(|&size| self.metadata_end(size))
+            .max()
+            .unwrap_or(0);
+        if let Some(data_offset) = requested {
+            if data_offset % 8 != 0
+                || data_offset < min
+                || member_sizes_sectors
+                    .iter()
+                    .any(|&size| self.data_size(size, data_offset) == 0)
+            {
+                return Err(MdError::InvalidDataOffset { data_offset, min });
+            }
+            return Ok(data_offset);
+        }
+        if *self == SuperblockVersion::V1_0 {
+            return Ok(0);
+        }
+        let smallest = member_sizes_sectors.iter().copied().min().unwrap_or(0);
+        let mut headroom: u64 = 128 * 1024 * 2;
+        while (headroom << 10) > smallest && headroom / 2 >= chunk_sectors as u64 * 4 {
+            headroom >>= 1;
+        }
+        Ok(headroom.max(min).next_multiple_of(2048))
+    }
+
+    /// Sectors available for data on a device of the given size
+    pub fn data_size(&self, device_size_sectors: u64, data_offset: u64) -> u64 {
+        match self {
+            // the bitmap and bad block log sit right before the superblock
+            SuperblockVersion::V1_0 => self
+                .super_offset(device_size_sectors)
+                .unwrap_or(0)
+                .saturating_sub(
+                    bitmap::reserved_sectors(device_size_sectors)
+                        + Self::BBLOG_SECTORS
+                        + data_offset,
+                ),
+            SuperblockVersion::V1_1 | SuperblockVersion::V1_2 => {
+                device_size_sectors.saturating_sub(data_offset)
+            }
+        }
+    }
+
+    /// Offset of the bad block log relative to the superblock, in 512b sectors.
+    /// Stored as a signed value.
+    fn bblog_offset(&self, device_size_sectors: u64) -> i32 {
+        match self {
+            SuperblockVersion::V1_0 => -(Self::BBLOG_SECTORS as i32),
+            // after the 4KiB superblock and the space reserved for a bitmap
+            SuperblockVersion::V1_1 | SuperblockVersion::V1_2 => {
+                (8 + bitmap::reserved_sectors(device_size_sectors)) as i32
+            }
+        }
+    }
+}
+
+bitflags::bitflags! {
+    /// MD_FEATURE_* bits stored in `ArrayInfo::feature_map`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct FeatureMap: u32 {
+        /// bitmap_offset is meaningful
+        const BITMAP_OFFSET = 1 << 0;
+        /// recovery_offset is meaningful
+        const RECOVERY_OFFSET = 1 << 1;
+        /// reshape_position, new_level, delta_disks, new_layout and new_chunk are meaningful
+        const RESHAPE_ACTIVE = 1 << 2;
+        /// the bad block log has entries
+        const BAD_BLOCKS = 1 << 3;
+        /// this device is replacing another device with the same role
+        const REPLACEMENT = 1 << 4;
+        /// the reshape is making the array smaller, so runs backwards
+        const RESHAPE_BACKWARDS = 1 << 5;
+        /// new_offset is meaningful
+        const NEW_OFFSET = 1 << 6;
+        /// recovery_offset is tracked through the bitmap
+        const RECOVERY_BITMAP = 1 << 7;
+        /// the bitmap is clustered, one per node
+        const CLUSTERED = 1 << 8;
+        /// the array has a write journal device
+        const JOURNAL = 1 << 9;
+        /// the array uses a partial parity log instead of a bitmap
+        const PPL = 1 << 10;
+        /// the partial parity log spans multiple entries
+        const MULTIPLE_PPLS = 1 << 11;
+        /// layout is meaningful for raid0
+        const RAID0_LAYOUT = 1 << 12;
+    }
 }
 
 impl ArrayInfo {
     const SUPERBLOCK_MAGIC: u32 = 0xa92b4efc;
     const MAJOR_VERSION: u32 = 1;
 
-    /// ctime must match for all members in the array
-    fn new(
-        uuid: Uuid,
-        name: &str,
-        ctime: DateTime<Utc>,
-        level: ArrayLevel,
-        layout: ArrayLayout,
-        size_bytes: u64,
-        block_size: u64,
-        raid_disks: u32,
-    ) -> ArrayInfo {
-        let chunksize = if level == ArrayLevel::Raid1 { 0 } else { 1024 };
-        let usable_disk_count = match level {
-            ArrayLevel::Raid0 => raid_disks,
-            ArrayLevel::Raid1 => raid_disks / 2,
-            ArrayLevel::Raid5 => raid_disks - 1,
-            ArrayLevel::Raid6 => raid_disks - 2,
-            _ => todo!("Other raid types"),
-        };
-
-        let data_offset = match level {
-            ArrayLevel::Raid1 => 0x800,  // why 1MB on top of superblock?
-            ArrayLevel::Raid5 => 0x1000, // why 2MB on top of superblock?
-            _ => todo!("unsupported"),
+    /// `data_offset` is the member's, in 512b sectors
+    fn new(config: &ArrayConfig, data_offset: u64) -> Result<ArrayInfo> {
+        let full_name = format!("{}:{}", config.host, config.name);
+        if full_name.len() > 32 {
+            return Err(MdError::NameTooLong {
+                name: full_name,
+                max: 32,
+            });
+        }
+        let level = config.level;
+        let raid_disks = config.raid_disks;
+        let layout = config.layout.unwrap_or_else(|| level.default_layout());
+        let min = level.min_raid_disks();
+        if raid_disks < min {
+            return Err(MdError::TooFewDevices {
+                level,
+                min,
+                found: raid_disks,
+            });
+        }
+        let valid_layout = match level {
+            ArrayLevel::Raid4 | ArrayLevel::Raid5 | ArrayLevel::Raid6 => {
+                ArrayLayout::from_raw(layout).is_some_and(|l| l.is_valid_for(level))
+            }
+            ArrayLevel::Raid10 => {
+                let copies = Raid10Layout::from(layout).copies();
+                copies != 0 && copies <= raid_disks
+            }
+            // original and alternate multi-zone layouts
+            ArrayLevel::Raid0 => layout <= 2,
+            ArrayLevel::Raid1 | ArrayLevel::Linear | ArrayLevel::Multipath => layout == 0,
         };
-        let size_blocks =
-            (size_bytes - (data_offset * block_size)) * usable_disk_count as u64 / block_size;
+        if !valid_layout {
+            return Err(MdError::InvalidLayout { level, layout });
+        }
+        let chunksize = level.chunk_sectors(config.chunk_kib)?;
 
-        let chunk_factor = if chunksize != 0 {
-            chunksize / block_size
-        } else {
-            1
+        // size is per member, the space each device contributes to the array.
+        // Like mdadm, only whole chunks are used
+        let available = config
+            .version
+            .data_size(config.size_bytes / config.block_size, data_offset);
+        let mut data_sectors = match config.component_size {
+            Some(size) if size > available => {
+                return Err(MdError::ComponentSizeTooLarge { size, available })
+            }
+            Some(size) => size,
+            None => available,
         };
-        ArrayInfo {
+        if chunksize != 0 {
+            data_sectors &= !(chunksize as u64 - 1);
+            if data_sectors == 0 {
+                return Err(MdError::InvalidChunkSize {
+                    level,
+                    kib: chunksize / 2,
+                });
+            }
+        }
+        Ok(ArrayInfo {
             magic: ArrayInfo::SUPERBLOCK_MAGIC,
             major_version: ArrayInfo::MAJOR_VERSION,
             feature_map: 0x0,
             _pad0: 0,
-            set_uuid: uuid.into_bytes(),
-            set_name: str_to_bytes(name),
-            ctime: instant_to_arrayinfo_format(ctime),
+            set_uuid: config.uuid.into_bytes(),
+            set_name: str_to_bytes(&full_name),
+            // must match for all members in the array
+            ctime: instant_to_arrayinfo_format(config.creation),
             level: level.into(),
-            layout: layout.into(),
-            size: size_blocks / chunk_factor,
-            chunksize: chunksize as u32,
+            layout,
+            size: data_sectors,
+            chunksize,
             raid_disks,
-            opaque_union_bitmap_offset_ppl: 0, // TODO?
-        }
+            bitmap_offset: 0,
+        })
     }
     pub fn creation(&self) -> chrono::NaiveDateTime {
-        let seconds: u64 = self.ctime & 0xffffffff; // bottom 40b
-        let micros: u32 = ((self.ctime & 0xffffff00000000) >> 40) as u32; // top 24b
-        DateTime::from_timestamp(seconds as i64, micros * 1000)
-            .unwrap()
-            .naive_local()
+        sb_time(self.ctime)
+    }
+    /// Usable size of the array in 512b sectors, assuming every member
+    /// contributes `size` sectors. None for unknown levels, and for fewer
+    /// members than the level needs or sizes that overflow, which only a
+    /// corrupt superblock holds
+    pub fn array_size(&self) -> Option<u64> {
+        let level = ArrayLevel::from_raw(self.level as i32)?;
+        if self.raid_disks < level.min_raid_disks() {
+            return None;
+        }
+        let raid_disks = self.raid_disks as u64;
+        match level {
+            ArrayLevel::Linear | ArrayLevel::Raid0 => self.size.checked_mul(raid_disks),
+            ArrayLevel::Raid1 | ArrayLevel::Multipath => Some(self.size),
+            ArrayLevel::Raid4 | ArrayLevel::Raid5 => self.size.checked_mul(raid_disks - 1),
+            ArrayLevel::Raid6 => self.size.checked_mul(raid_disks - 2),
+            ArrayLevel::Raid10 => {
+                let copies = Raid10Layout::from(self.layout).copies();
+                Some(self.size.checked_mul(raid_disks)? / copies.max(1) as u64)
+            }
+        }
     }
     pub fn name(&self) -> Result<String, FromUtf8Error> {
         let filtered: Vec<u8> = self
@@ -149,50 +788,175 @@ impl ArrayInfo {
         Uuid::from_slice(&self.set_uuid).unwrap()
     }
     pub fn as_bytes(&self) -> [u8; 100] {
-        unsafe { std::mem::transmute(*self) }
-    }
-    pub fn from_bytes(buf: &[u8; 100]) -> io::Result<Self> {
-        let res: Self = unsafe { std::mem::transmute(*buf) };
-        let magic = res.magic;
-        if magic != ArrayInfo::SUPERBLOCK_MAGIC {
-            return Err(Error::new(
-                io::ErrorKind::InvalidData,
-                format!("Invalid Magic, got {:x}", magic),
-            ));
+        let mut buf = [0; 100];
+        let (
+            magic,
+            major_version,
+            feature_map,
+            pad0,
+            set_uuid,
+            set_name,
+            ctime,
+            level,
+            layout,
+            size,
+            chunksize,
+            raid_disks,
+            bitmap_offset,
+        ) = mut_array_refs![&mut buf, 4, 4, 4, 4, 16, 32, 8, 4, 4, 8, 4, 4, 4];
+        *magic = self.magic.to_le_bytes();
+        *major_version = self.major_version.to_le_bytes();
+        *feature_map = self.feature_map.to_le_bytes();
+        *pad0 = self._pad0.to_le_bytes();
+        *set_uuid = self.set_uuid;
+        *set_name = self.set_name;
+        *ctime = self.ctime.to_le_bytes();
+        *level = self.level.to_le_bytes();
+        *layout = self.layout.to_le_bytes();
+        *size = self.size.to_le_bytes();
+        *chunksize = self.chunksize.to_le_bytes();
+        *raid_disks = self.raid_disks.to_le_bytes();
+        *bitmap_offset = self.bitmap_offset.to_le_bytes();
+        buf
+    }
+    pub fn from_bytes(buf: &[u8; 100]) -> Result<Self> {
+        let (
+            magic,
+            major_version,
+            feature_map,
+            pad0,
+            set_uuid,
+            set_name,
+            ctime,
+            level,
+            layout,
+            size,
+            chunksize,
+            raid_disks,
+            bitmap_offset,
+        ) = array_refs![buf, 4, 4, 4, 4, 16, 32, 8, 4, 4, 8, 4, 4, 4];
+        let res = ArrayInfo {
+            magic: u32::from_le_bytes(*magic),
+            major_version: u32::from_le_bytes(*major_version),
+            feature_map: u32::from_le_bytes(*feature_map),
+            _pad0: u32::from_le_bytes(*pad0),
+            set_uuid: *set_uuid,
+            set_name: *set_name,
+            ctime: u64::from_le_bytes(*ctime),
+            level: u32::from_le_bytes(*level),
+            layout: u32::from_le_bytes(*layout),
+            size: u64::from_le_bytes(*size),
+            chunksize: u32::from_le_bytes(*chunksize),
+            raid_disks: u32::from_le_bytes(*raid_disks),
+            bitmap_offset: u32::from_le_bytes(*bitmap_offset),
+        };
+        if res.magic != ArrayInfo::SUPERBLOCK_MAGIC {
+            return Err(MdError::BadMagic {
+                what: "superblock",
+                found: res.magic,
+            });
+        }
+        if res.major_version != ArrayInfo::MAJOR_VERSION {
+            return Err(MdError::UnsupportedVersion {
+                what: "superblock",
+                found: res.major_version,
+            });
         }
-        let major_version = res.major_version;
-        if major_version != ArrayInfo::MAJOR_VERSION {
-            return Err(Error::new(
-                io::ErrorKind::InvalidData,
-                format!("Invalid major version, got {:x}", major_version),
+        // like the kernel, refuse superblocks using features we don't know about
+        if FeatureMap::from_bits(res.feature_map).is_none() {
+            return Err(MdError::UnsupportedFeatures(
+                res.feature_map & !FeatureMap::all().bits(),
             ));
         }
         Ok(res)
     }
+    /// Offset of the internal bitmap from the superblock, in 512b sectors
+    pub fn bitmap_offset(&self) -> Option<i32> {
+        if self.features().contains(FeatureMap::BITMAP_OFFSET) {
+            Some(self.bitmap_offset as i32)
+        } else {
+            None
+        }
+    }
+    pub fn features(&self) -> FeatureMap {
+        FeatureMap::from_bits_retain(self.feature_map)
+    }
+    pub fn set_features(&mut self, features: FeatureMap) {
+        self.feature_map = features.bits();
+    }
 }
 
-#[repr(C, packed)]
-#[derive(Debug, Clone, Copy)]
+/// Reshape fields, bytes 100..128 of the superblock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FeatureBit4 {
+    #[cfg_attr(feature = "serde", serde(with = "serde_fields::level"))]
     pub new_level: u32,
     pub reshape_position: u64,
+    #[cfg_attr(feature = "serde", serde(with = "serde_fields::signed"))]
     pub delta_disks: u32,
     pub new_layout: u32,
     pub new_chunk: u32,
+    #[cfg_attr(feature = "serde", serde(with = "serde_fields::signed"))]
     pub new_offset: u32,
 }
 
 impl FeatureBit4 {
     pub fn as_bytes(&self) -> [u8; 28] {
-        unsafe { std::mem::transmute(*self) }
+        let mut buf = [0; 28];
+        let (new_level, reshape_position, delta_disks, new_layout, new_chunk, new_offset) =
+            mut_array_refs![&mut buf, 4, 8, 4, 4, 4, 4];
+        *new_level = self.new_level.to_le_bytes();
+        *reshape_position = self.reshape_position.to_le_bytes();
+        *delta_disks = self.delta_disks.to_le_bytes();
+        *new_layout = self.new_layout.to_le_bytes();
+        *new_chunk = self.new_chunk.to_le_bytes();
+        *new_offset = self.new_offset.to_le_bytes();
+        buf
     }
-    pub fn from_bytes(buf: &[u8; 28]) -> io::Result<Self> {
-        let res: Self = unsafe { std::mem::transmute(*buf) };
-        Ok(res)
+    pub fn from_bytes(buf: &[u8; 28]) -> Result<Self> {
+        let (new_level, reshape_position, delta_disks, new_layout, new_chunk, new_offset) =
+            array_refs![buf, 4, 8, 4, 4, 4, 4];
+        Ok(FeatureBit4 {
+            new_level: u32::from_le_bytes(*new_level),
+            reshape_position: u64::from_le_bytes(*reshape_position),
+            delta_disks: u32::from_le_bytes(*delta_disks),
+            new_layout: u32::from_le_bytes(*new_layout),
+            new_chunk: u32::from_le_bytes(*new_chunk),
+            new_offset: u32::from_le_bytes(*new_offset),
+        })
+    }
+}
+
+/// Progress of an in-flight reshape, as recorded in the superblock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReshapeState {
+    /// Level the array is being converted to
+    pub new_level: u32,
+    /// Array sector up to which the reshape has progressed
+    pub reshape_position: u64,
+    /// Change in raid_disks, negative when shrinking
+    pub delta_disks: i32,
+    pub new_layout: u32,
+    /// In 512b sectors
+    pub new_chunk: u32,
+    /// Signed change of data_offset, in sectors. Only set when the data is
+    /// being moved, FeatureMap::NEW_OFFSET
+    pub new_offset: Option<i32>,
+    /// Whether the reshape runs from the end of the array towards the start
+    pub backwards: bool,
+}
+
+impl ReshapeState {
+    /// raid_disks before the reshape started. The superblock's raid_disks
+    /// already holds the count the array is being reshaped to
+    pub fn old_raid_disks(&self, raid_disks: u32) -> u32 {
+        (raid_disks as i64 - self.delta_disks as i64) as u32
     }
 }
-#[repr(C)]
-#[derive(Debug, Clone, Copy)]
+/// Per-device information, bytes 128..192 of the superblock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DeviceInfo {
     pub data_offset: u64,
     pub data_size: u64,
@@ -202,10 +966,15 @@ pub struct DeviceInfo {
     pub recovery_offset: u64, // Using recovery_offset instead of the union
     pub dev_number: u32,
     pub cnt_corrected_read: u32,
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "uuid", with = "serde_fields::uuid_bytes")
+    )]
     pub device_uuid: [u8; 16],
     pub devflags: u8,
     pub bblog_shift: u8,
     pub bblog_size: u16,
+    #[cfg_attr(feature = "serde", serde(with = "serde_fields::signed"))]
     pub bblog_offset: u32,
 }
 
@@ -216,73 +985,212 @@ impl DeviceInfo {
         data_offset_blocks: u64,
         dev_number: u32,
         device_uuid: Option<Uuid>,
+        version: SuperblockVersion,
     ) -> Self {
         let device_size_blocks = device_size_bytes / block_size;
         DeviceInfo {
             data_offset: data_offset_blocks,
-            data_size: device_size_blocks - data_offset_blocks,
-            super_offset: 0x8, // 8* 512b block = 4KiB = 0x1000
+            data_size: version.data_size(device_size_blocks, data_offset_blocks),
+            super_offset: version.super_offset(device_size_blocks).unwrap_or(0),
             recovery_offset: 0,
             dev_number,
             cnt_corrected_read: 0, // Initialize to 0
             device_uuid: device_uuid.unwrap_or_else(Uuid::new_v4).into_bytes(),
             devflags: 0,
             bblog_shift: 0,
-            bblog_size: 8,    // ?
-            bblog_offset: 16, // ?
+            bblog_size: SuperblockVersion::BBLOG_SECTORS as u16,
+            bblog_offset: version.bblog_offset(device_size_blocks) as u32,
         }
     }
 
     pub fn as_bytes(&self) -> [u8; 64] {
-        unsafe { std::mem::transmute(*self) }
+        let mut buf = [0; 64];
+        let (
+            data_offset,
+            data_size,
+            super_offset,
+            recovery_offset,
+            dev_number,
+            cnt_corrected_read,
+            device_uuid,
+            devflags,
+            bblog_shift,
+            bblog_size,
+            bblog_offset,
+        ) = mut_array_refs![&mut buf, 8, 8, 8, 8, 4, 4, 16, 1, 1, 2, 4];
+        *data_offset = self.data_offset.to_le_bytes();
+        *data_size = self.data_size.to_le_bytes();
+        *super_offset = self.super_offset.to_le_bytes();
+        *recovery_offset = self.recovery_offset.to_le_bytes();
+        *dev_number = self.dev_number.to_le_bytes();
+        *cnt_corrected_read = self.cnt_corrected_read.to_le_bytes();
+        *device_uuid = self.device_uuid;
+        *devflags = [self.devflags];
+        *bblog_shift = [self.bblog_shift];
+        *bblog_size = self.bblog_size.to_le_bytes();
+        *bblog_offset = self.bblog_offset.to_le_bytes();
+        buf
     }
-    pub fn from_bytes(buf: &[u8; 64]) -> io::Result<Self> {
-        let res: Self = unsafe { std::mem::transmute(*buf) };
-        Ok(res)
+    pub fn from_bytes(buf: &[u8; 64]) -> Result<Self> {
+        let (
+            data_offset,
+            data_size,
+            super_offset,
+            recovery_offset,
+            dev_number,
+            cnt_corrected_read,
+            device_uuid,
+            devflags,
+            bblog_shift,
+            bblog_size,
+            bblog_offset,
+        ) = array_refs![buf, 8, 8, 8, 8, 4, 4, 16, 1, 1, 2, 4];
+        Ok(DeviceInfo {
+            data_offset: u64::from_le_bytes(*data_offset),
+            data_size: u64::from_le_bytes(*data_size),
+            super_offset: u64::from_le_bytes(*super_offset),
+            recovery_offset: u64::from_le_bytes(*recovery_offset),
+            dev_number: u32::from_le_bytes(*dev_number),
+            cnt_corrected_read: u32::from_le_bytes(*cnt_corrected_read),
+            device_uuid: *device_uuid,
+            devflags: devflags[0],
+            bblog_shift: bblog_shift[0],
+            bblog_size: u16::from_le_bytes(*bblog_size),
+            bblog_offset: u32::from_le_bytes(*bblog_offset),
+        })
     }
     pub fn uuid(&self) -> Uuid {
         Uuid::from_slice(&self.device_uuid).unwrap()
     }
 }
 
-#[repr(C)]
-#[derive(Debug, Clone, Copy)]
+/// Array state, bytes 192..256 of the superblock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ArrayStateInfo {
+    #[cfg_attr(feature = "serde", serde(with = "serde_fields::timestamp"))]
     pub utime: u64,
     pub events: u64,
     pub resync_offset: u64,
     pub sb_csum: u32,
     pub max_dev: u32,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "serde_fields::is_zero")
+    )]
     pub pad3: [u8; 32],
 }
 
 impl ArrayStateInfo {
-    pub fn from_bytes(buf: &[u8; 64]) -> io::Result<Self> {
-        let res: Self = unsafe { std::mem::transmute(*buf) };
-        Ok(res)
+    pub fn update_time(&self) -> chrono::NaiveDateTime {
+        sb_time(self.utime)
+    }
+    pub fn from_bytes(buf: &[u8; 64]) -> Result<Self> {
+        let (utime, events, resync_offset, sb_csum, max_dev, pad3) =
+            array_refs![buf, 8, 8, 8, 4, 4, 32];
+        Ok(ArrayStateInfo {
+            utime: u64::from_le_bytes(*utime),
+            events: u64::from_le_bytes(*events),
+            resync_offset: u64::from_le_bytes(*resync_offset),
+            sb_csum: u32::from_le_bytes(*sb_csum),
+            max_dev: u32::from_le_bytes(*max_dev),
+            pad3: *pad3,
+        })
     }
     pub fn as_bytes(&self) -> [u8; 64] {
-        unsafe { std::mem::transmute(*self) }
+        let mut buf = [0; 64];
+        let (utime, events, resync_offset, sb_csum, max_dev, pad3) =
+            mut_array_refs![&mut buf, 8, 8, 8, 4, 4, 32];
+        *utime = self.utime.to_le_bytes();
+        *events = self.events.to_le_bytes();
+        *resync_offset = self.resync_offset.to_le_bytes();
+        *sb_csum = self.sb_csum.to_le_bytes();
+        *max_dev = self.max_dev.to_le_bytes();
+        *pad3 = self.pad3;
+        buf
     }
 }
 
+/// Settings shared by every member of a new array, see `MdpSuperblock1::new`.
+/// `create::ArrayBuilder` fills these in from the member devices
 #[derive(Debug, Clone)]
+pub struct ArrayConfig {
+    pub host: String,
+    pub name: String,
+    pub uuid: Uuid,
+    pub creation: DateTime<Utc>,
+    pub level: ArrayLevel,
+    /// Raw layout word, like `ArrayLayout::ParityFirst.into()` or
+    /// `Raid10Layout::far(2).into()`. None for the level's default layout
+    pub layout: Option<u32>,
+    /// None for 512KiB on striped levels
+    pub chunk_kib: Option<u32>,
+    pub raid_disks: u32,
+    /// Size of the smallest member
+    pub size_bytes: u64,
+    pub block_size: u64,
+    /// Space used on each member in 512b sectors, like mdadm's `--size`. None
+    /// for everything available on the smallest member, larger members keep
+    /// the rest unused after the data
+    pub component_size: Option<u64>,
+    pub version: SuperblockVersion,
+    pub internal_bitmap: bool,
+}
+
+impl ArrayConfig {
+    /// A v1.2 array created now with a random uuid, the level's default
+    /// layout and chunk size, and no bitmap
+    pub fn new(
+        host: &str,
+        name: &str,
+        level: ArrayLevel,
+        raid_disks: u32,
+        size_bytes: u64,
+    ) -> Self {
+        ArrayConfig {
+            host: host.to_string(),
+            name: name.to_string(),
+            uuid: Uuid::new_v4(),
+            creation: Utc::now(),
+            level,
+            layout: None,
+            chunk_kib: None,
+            raid_disks,
+            size_bytes,
+            block_size: 512,
+            component_size: None,
+            version: SuperblockVersion::V1_2,
+            internal_bitmap: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MdpSuperblock1 {
     pub array_info: ArrayInfo,
     pub feature_bit4: FeatureBit4,
     pub device_info: DeviceInfo,
     pub array_state_info: ArrayStateInfo,
+    #[cfg_attr(feature = "serde", serde(with = "serde_fields::dev_roles"))]
     pub dev_roles: Vec<u16>,
 }
 
 impl MdpSuperblock1 {
     pub const MAX_SIZE: usize = 4096;
-    pub fn from_bytes(buf: &[u8]) -> io::Result<Self> {
+    /// Parse and verify the checksum
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        let sb = Self::from_bytes_lenient(buf)?;
+        sb.verify_sb_csum()?;
+        Ok(sb)
+    }
+
+    /// Parse without verifying the checksum, to inspect damaged superblocks
+    pub fn from_bytes_lenient(buf: &[u8]) -> Result<Self> {
         if buf.len() < 256 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Buffer too short for MdpSuperblock1",
-            ));
+            return Err(MdError::Truncated {
+                what: "MdpSuperblock1",
+            });
         }
 
         let array_info = ArrayInfo::from_bytes(array_ref!(buf, 0, 100))?;
@@ -293,10 +1201,7 @@ impl MdpSuperblock1 {
         let dev_roles_count = array_state_info.max_dev as usize;
 
         if buf.len() < 256 + dev_roles_count * 2 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Buffer too short for dev_roles",
-            ));
+            return Err(MdError::Truncated { what: "dev_roles" });
         }
 
         // fixed-size part of the superblock is 256b
@@ -315,42 +1220,27 @@ impl MdpSuperblock1 {
         })
     }
 
-    pub fn new(
-        host: &str,
-        name: &str,
-        uuid: Option<Uuid>,
-        creation: DateTime<Utc>,
-        size_bytes: u64,
-        block_size: u64,
-        disk_count: u32,
-        device_info: DeviceInfo,
-        raid_level: ArrayLevel,
-    ) -> Result<MdpSuperblock1, impl std::error::Error> {
-        if host.len() + name.len() > 32 {
-            return Err(std::io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Length of host + name must be <=32",
-            ));
-        }
-        let array_uuid = uuid.unwrap_or_else(Uuid::new_v4);
-        let layout = match raid_level {
-            // default? unclear, should be ignored
-            ArrayLevel::Raid1 => ArrayLayout::LeftAsymmetric,
-            _ => ArrayLayout::LeftSymmetric,
-        };
+    /// Superblock for member `device_info` of the array described by `config`.
+    /// `device_info` should use the data offset from
+    /// `SuperblockVersion::data_offset`, `ArrayInfo::size` is computed with it
+    pub fn new(config: &ArrayConfig, device_info: DeviceInfo) -> Result<MdpSuperblock1> {
+        let mut array_info = ArrayInfo::new(config, device_info.data_offset)?;
 
-        let array_info = ArrayInfo::new(
-            array_uuid,
-            &format!("{host}:{name}"),
-            creation,
-            raid_level,
-            layout,
-            size_bytes,
-            block_size,
-            disk_count,
-        );
+        if config.internal_bitmap {
+            let bitmap_offset = match config.version {
+                // right after the 4KiB superblock
+                SuperblockVersion::V1_1 | SuperblockVersion::V1_2 => 8,
+                // right after the data, the bad block log follows
+                SuperblockVersion::V1_0 => {
+                    (device_info.data_offset + device_info.data_size) as i64
+                        - device_info.super_offset as i64
+                }
+            };
+            array_info.bitmap_offset = bitmap_offset as u32;
+            array_info.set_features(array_info.features() | FeatureMap::BITMAP_OFFSET);
+        }
 
-        // Create dummy FeatureBit4 -- no idea what for
+        // Reshape fields, only meaningful with FeatureMap::RESHAPE_ACTIVE
         let feature_bit4 = FeatureBit4 {
             new_level: 0,
             reshape_position: 0,
@@ -362,7 +1252,7 @@ impl MdpSuperblock1 {
 
         let max_dev = 0x80; // 128
         let array_state_info = ArrayStateInfo {
-            utime: instant_to_arrayinfo_format(creation),
+            utime: instant_to_arrayinfo_format(config.creation),
             events: 18, // why?
             resync_offset: 0xffffffffffffffff,
             sb_csum: 0,
@@ -370,9 +1260,9 @@ impl MdpSuperblock1 {
             pad3: [0; 32],
         };
 
-        let mut dev_roles = vec![0xffff as u16; max_dev as usize];
-        for i in 0..disk_count {
-            dev_roles[i as usize] = i as u16;
+        let mut dev_roles = vec![DeviceRole::Spare.into(); max_dev as usize];
+        for i in 0..config.raid_disks {
+            dev_roles[i as usize] = DeviceRole::Active(i as u16).into();
         }
 
         let mut sb = MdpSuperblock1 {
@@ -382,11 +1272,23 @@ impl MdpSuperblock1 {
             array_state_info,
             dev_roles,
         };
-        let csum = sb.calculate_sb_csum();
-        sb.array_state_info.sb_csum = csum;
+        sb.update_sb_csum();
         Ok(sb)
     }
 
+    pub fn update_sb_csum(&mut self) {
+        self.array_state_info.sb_csum = self.calculate_sb_csum();
+    }
+
+    pub fn verify_sb_csum(&self) -> Result<()> {
+        let expected = self.array_state_info.sb_csum;
+        let computed = self.calculate_sb_csum();
+        if expected != computed {
+            return Err(MdError::ChecksumMismatch { expected, computed });
+        }
+        Ok(())
+    }
+
     pub fn calculate_sb_csum(&self) -> u32 {
         // checksum is calculated with checksum set to 0
         let mut new_sb = self.clone();
@@ -423,44 +1325,327 @@ impl MdpSuperblock1 {
         ret.extend_from_slice(&byte_vec);
         ret
     }
-    pub fn from_file(path: &str, offset: u64) -> io::Result<Self> {
+    pub fn from_file(path: &str, offset: u64) -> Result<Self> {
+        let sb = Self::from_file_lenient(path, offset)?;
+        sb.verify_sb_csum()?;
+        Ok(sb)
+    }
+    pub fn from_file_lenient(path: &str, offset: u64) -> Result<Self> {
         let mut file = File::open(path)?;
 
         file.seek(SeekFrom::Start(offset))?;
         let mut buf = [0; Self::MAX_SIZE];
         file.read_exact(&mut buf)?;
 
-        Self::from_bytes(&buf)
+        Self::from_bytes_lenient(&buf)
+    }
+
+    /// Location of the internal bitmap on the device, in bytes
+    pub fn bitmap_location(&self) -> Option<u64> {
+        let bitmap_offset = self.array_info.bitmap_offset()?;
+        let sector = self.device_info.super_offset as i64 + bitmap_offset as i64;
+        Some(sector as u64 * 512)
+    }
+
+    /// Location of the bad block log on the device, in bytes
+    pub fn bblog_location(&self) -> Option<u64> {
+        let bblog_offset = self.device_info.bblog_offset as i32;
+        if bblog_offset == 0 || self.device_info.bblog_size == 0 {
+            return None;
+        }
+        let sector = self.device_info.super_offset as i64 + bblog_offset as i64;
+        Some(sector as u64 * 512)
+    }
+
+    /// Sectors available for the bitmap, up to the bad block log. Without
+    /// one, as older mdadm writes, up to the data (1.1/1.2) or the superblock
+    /// (1.0)
+    pub(crate) fn bitmap_room(&self) -> Option<u64> {
+        let bitmap_offset = self.array_info.bitmap_offset()?;
+        let dev = &self.device_info;
+        // relative to the superblock, like the offsets
+        let end = if self.bblog_location().is_some() {
+            dev.bblog_offset as i32 as i64
+        } else if dev.data_offset < dev.super_offset {
+            0
+        } else {
+            dev.data_offset as i64 - dev.super_offset as i64
+        };
+        u64::try_from(end - bitmap_offset as i64).ok()
+    }
+
+    /// Bitmap superblock to write alongside a newly created superblock
+    pub fn new_bitmap(&self) -> Option<BitmapSuperblock> {
+        let sync_size = self.array_info.size;
+        let chunksize = BitmapSuperblock::choose_chunksize(sync_size, self.bitmap_room()?)?;
+        Some(BitmapSuperblock::new(
+            self.array_info.uuid(),
+            self.array_state_info.events,
+            sync_size,
+            chunksize,
+        ))
+    }
+
+    /// Role of the device this superblock was read from
+    pub fn role(&self) -> DeviceRole {
+        self.role_of(self.device_info.dev_number)
+    }
+
+    /// Role of any member, by dev_number. Devices without an entry are spares
+    pub fn role_of(&self, dev_number: u32) -> DeviceRole {
+        self.dev_roles
+            .get(dev_number as usize)
+            .map_or(DeviceRole::Spare, |&role| role.into())
+    }
+
+    pub fn set_role(&mut self, dev_number: u32, role: DeviceRole) {
+        let idx = dev_number as usize;
+        if idx >= self.dev_roles.len() {
+            self.dev_roles.resize(idx + 1, DeviceRole::Spare.into());
+            self.array_state_info.max_dev = self.dev_roles.len() as u32;
+        }
+        self.dev_roles[idx] = role.into();
+    }
+
+    /// One character per slot, as in mdadm's "Array State":
+    /// 'A' active, '.' missing, 'R' active with a replacement in progress,
+    /// '?' claimed by more members than that allows
+    pub fn array_state(&self) -> String {
+        // slots being removed by a reshape are shown too
+        let extra = self.reshape().map_or(0, |r| (-r.delta_disks).max(0) as u32);
+        let replacing = self.array_info.features().contains(FeatureMap::REPLACEMENT);
+        (0..self.array_info.raid_disks + extra)
+            .map(|slot| {
+                let count = self
+                    .dev_roles
+                    .iter()
+                    .filter(|&&role| role as u32 == slot)
+                    .count();
+                match count {
+                    0 => '.',
+                    1 => 'A',
+                    2 if replacing => 'R',
+                    _ => '?',
+                }
+            })
+            .collect()
+    }
+
+    /// Reshape progress, if a reshape was interrupted
+    pub fn reshape(&self) -> Option<ReshapeState> {
+        let features = self.array_info.features();
+        if !features.contains(FeatureMap::RESHAPE_ACTIVE) {
+            return None;
+        }
+        let fb4 = self.feature_bit4;
+        Some(ReshapeState {
+            new_level: fb4.new_level,
+            reshape_position: fb4.reshape_position,
+            delta_disks: fb4.delta_disks as i32,
+            new_layout: fb4.new_layout,
+            new_chunk: fb4.new_chunk,
+            new_offset: features
+                .contains(FeatureMap::NEW_OFFSET)
+                .then_some(fb4.new_offset as i32),
+            backwards: features.contains(FeatureMap::RESHAPE_BACKWARDS),
+        })
+    }
+
+    /// Superblock version implied by where this superblock was stored
+    pub fn version(&self) -> SuperblockVersion {
+        SuperblockVersion::from_super_offset(self.device_info.super_offset)
+    }
+
+    /// Look for a v1.x superblock at every location a 1.0, 1.1 or 1.2
+    /// superblock could be stored
+    pub fn probe(path: &str) -> Result<(SuperblockVersion, Self)> {
+        let mut file = File::open(path)?;
+        // works for both block devices and regular files
+        let device_size_sectors = file.seek(SeekFrom::End(0))? / 512;
+
+        for version in SuperblockVersion::ALL {
+            let Some(super_offset) = version.super_offset(device_size_sectors) else {
+                continue;
+            };
+            let mut buf = [0; Self::MAX_SIZE];
+            file.seek(SeekFrom::Start(super_offset * 512))?;
+            if file.read_exact(&mut buf).is_err() {
+                continue;
+            }
+            match Self::from_bytes_lenient(&buf) {
+                // a superblock records where it was written, reject
+                // matches that don't agree with the probed location
+                Ok(sb) if sb.device_info.super_offset == super_offset => {
+                    // this is an md superblock, a bad checksum means it's
+                    // corrupt rather than missing
+                    sb.verify_sb_csum()?;
+                    return Ok((version, sb));
+                }
+                _ => continue,
+            }
+        }
+        Err(MdError::NoSuperblock {
+            path: path.to_string(),
+        })
+    }
+}
+
+/// Any superblock format that can be found on a member device
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)] // there's one per member device
+pub enum Superblock {
+    V0_90(MdpSuperblock090),
+    V1(SuperblockVersion, MdpSuperblock1),
+}
+
+impl Superblock {
+    /// Look for a v1.x superblock, falling back to v0.90
+    pub fn probe(path: &str) -> Result<Self> {
+        match MdpSuperblock1::probe(path) {
+            Ok((version, sb)) => Ok(Superblock::V1(version, sb)),
+            Err(v1_err) => MdpSuperblock090::probe(path)
+                .map(Superblock::V0_90)
+                .map_err(|_| v1_err),
+        }
+    }
+    pub fn major_version(&self) -> u32 {
+        match self {
+            Superblock::V0_90(sb) => sb.major_version,
+            Superblock::V1(..) => ArrayInfo::MAJOR_VERSION,
+        }
+    }
+    pub fn minor_version(&self) -> u32 {
+        match self {
+            Superblock::V0_90(sb) => sb.minor_version,
+            Superblock::V1(version, _) => version.minor_version(),
+        }
+    }
+    pub fn uuid(&self) -> Uuid {
+        match self {
+            Superblock::V0_90(sb) => sb.uuid(),
+            Superblock::V1(_, sb) => sb.array_info.uuid(),
+        }
+    }
+    pub fn events(&self) -> u64 {
+        match self {
+            Superblock::V0_90(sb) => sb.events,
+            Superblock::V1(_, sb) => sb.array_state_info.events,
+        }
+    }
+    pub fn update_time(&self) -> chrono::NaiveDateTime {
+        match self {
+            Superblock::V0_90(sb) => sb.update_time(),
+            Superblock::V1(_, sb) => sb.array_state_info.update_time(),
+        }
+    }
+    /// Set the event count, as `mdadm --assemble --force` does to bring a
+    /// stale member back. The checksum is updated by `write`
+    pub fn set_events(&mut self, events: u64) {
+        match self {
+            Superblock::V0_90(sb) => sb.events = events,
+            Superblock::V1(_, sb) => sb.array_state_info.events = events,
+        }
+    }
+    /// Write back to the location on `path` it was found at, with an updated checksum
+    pub fn write(&mut self, path: &str) -> Result<()> {
+        let mut file = std::fs::OpenOptions::new().write(true).open(path)?;
+        let (offset, bytes) = match self {
+            Superblock::V0_90(sb) => {
+                let device_size = file.seek(SeekFrom::End(0))?;
+                let Some(offset) = MdpSuperblock090::offset(device_size) else {
+                    return Err(MdError::NoSuperblock {
+                        path: path.to_string(),
+                    });
+                };
+                sb.sb_csum = sb.calculate_sb_csum();
+                (offset, sb.as_bytes())
+            }
+            Superblock::V1(_, sb) => {
+                sb.update_sb_csum();
+                (sb.device_info.super_offset * 512, sb.as_bytes())
+            }
+        };
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        Ok(())
+    }
+    /// None for levels this crate doesn't know about
+    pub fn level(&self) -> Option<ArrayLevel> {
+        match self {
+            Superblock::V0_90(sb) => ArrayLevel::from_raw(sb.level as i32),
+            Superblock::V1(_, sb) => ArrayLevel::from_raw(sb.array_info.level as i32),
+        }
+    }
+    pub fn layout(&self) -> u32 {
+        match self {
+            Superblock::V0_90(sb) => sb.layout,
+            Superblock::V1(_, sb) => sb.array_info.layout,
+        }
+    }
+    pub fn raid_disks(&self) -> u32 {
+        match self {
+            Superblock::V0_90(sb) => sb.raid_disks,
+            Superblock::V1(_, sb) => sb.array_info.raid_disks,
+        }
+    }
+    pub fn role(&self) -> DeviceRole {
+        match self {
+            Superblock::V0_90(sb) => sb.role(),
+            Superblock::V1(_, sb) => sb.role(),
+        }
+    }
+    /// Index of this device within the array metadata, independent of its role
+    pub fn dev_number(&self) -> u32 {
+        match self {
+            Superblock::V0_90(sb) => sb.this_disk.number,
+            Superblock::V1(_, sb) => sb.device_info.dev_number,
+        }
+    }
+    pub fn reshape(&self) -> Option<ReshapeState> {
+        match self {
+            Superblock::V0_90(sb) => sb.reshape(),
+            Superblock::V1(_, sb) => sb.reshape(),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::*;
+
+    /// A test array named testhost:testarray, with 10MiB members
+    pub(crate) fn config(level: ArrayLevel, raid_disks: u32) -> ArrayConfig {
+        ArrayConfig::new("testhost", "testarray", level, raid_disks, 10 << 20)
+    }
+
+    /// Member `dev_number` of `config`, as large as the smallest member
+    pub(crate) fn member(
+        config: &ArrayConfig,
+        data_offset: u64,
+        dev_number: u32,
+    ) -> Result<MdpSuperblock1> {
+        let device_info = DeviceInfo::new(
+            config.size_bytes,
+            config.block_size,
+            data_offset,
+            dev_number,
+            None,
+            config.version,
+        );
+        MdpSuperblock1::new(config, device_info)
+    }
+
     #[test]
     fn test_mdp_superblock1_as_bytes_from_bytes_roundtrip() {
-        let host = "testhost";
-        let name = "testarray";
-        let uuid = Some(Uuid::new_v4());
-        let creation = Utc::now();
-        let size_bytes = 1024 * 1024 * 100; // 100 MB
-        let block_size = 512;
-        let disk_count = 2;
-        let device_info = DeviceInfo::new(size_bytes, block_size, 4096, 0, None);
-        let raid_level = ArrayLevel::Raid1;
-
-        let original_sb = MdpSuperblock1::new(
-            host,
-            name,
-            uuid,
-            creation,
-            size_bytes,
-            block_size,
-            disk_count,
-            device_info,
-            raid_level,
-        )
-        .unwrap();
+        let config = ArrayConfig::new(
+            "testhost",
+            "testarray",
+            ArrayLevel::Raid1,
+            2,
+            1024 * 1024 * 100, // 100 MB
+        );
+        let original_sb = member(&config, 4096, 0).unwrap();
 
         let bytes = original_sb.as_bytes();
         let reconstructed_sb = MdpSuperblock1::from_bytes(&bytes).unwrap();
@@ -485,4 +1670,541 @@ mod tests {
             reconstructed_sb.array_state_info.events
         );
     }
+
+    #[test]
+    fn test_superblock_version_offsets() {
+        // 10MiB device
+        let sectors = 20480;
+        assert_eq!(SuperblockVersion::V1_2.super_offset(sectors), Some(8));
+        assert_eq!(SuperblockVersion::V1_1.super_offset(sectors), Some(0));
+        assert_eq!(SuperblockVersion::V1_0.super_offset(sectors), Some(20464));
+        // not 4KiB aligned
+        assert_eq!(SuperblockVersion::V1_0.super_offset(20483), Some(20464));
+        assert_eq!(SuperblockVersion::V1_0.super_offset(10), None);
+
+        for version in SuperblockVersion::ALL {
+            let offset = version.super_offset(sectors).unwrap();
+            assert_eq!(SuperblockVersion::from_super_offset(offset), version);
+        }
+    }
+
+    #[test]
+    fn test_v1_0_data_area_ends_before_metadata() {
+        let size_bytes = 10 * 1024 * 1024;
+        let device_info = DeviceInfo::new(size_bytes, 512, 0, 0, None, SuperblockVersion::V1_0);
+        let data_offset = device_info.data_offset;
+        let data_size = device_info.data_size;
+        let super_offset = device_info.super_offset;
+        let bblog_offset = device_info.bblog_offset as i32;
+        assert_eq!(data_offset, 0);
+        assert_eq!(super_offset, 20464);
+        assert_eq!(bblog_offset, -8);
+        // 4KiB bitmap space, then 4KiB of bad block log
+        assert_eq!(data_size, super_offset - 8 - 8);
+    }
+
+    #[test]
+    fn test_probe_v1_0() {
+        let config = ArrayConfig {
+            version: SuperblockVersion::V1_0,
+            ..config(ArrayLevel::Raid1, 2)
+        };
+        let sb = member(&config, 0, 0).unwrap();
+
+        let mut image = vec![0u8; config.size_bytes as usize];
+        let offset = sb.device_info.super_offset as usize * 512;
+        let bytes = sb.as_bytes();
+        image[offset..offset + bytes.len()].copy_from_slice(&bytes);
+        let path = std::env::temp_dir().join(format!("probe_v1_0-{}", std::process::id()));
+        std::fs::write(&path, image).unwrap();
+
+        let (found, probed) = MdpSuperblock1::probe(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(found, SuperblockVersion::V1_0);
+        assert_eq!(probed.array_info.uuid(), sb.array_info.uuid());
+    }
+
+    #[test]
+    fn test_unknown_feature_bits_are_rejected() {
+        let config = config(ArrayLevel::Raid1, 2);
+        let mut sb = member(&config, 0x800, 0).unwrap();
+
+        sb.array_info
+            .set_features(FeatureMap::BITMAP_OFFSET | FeatureMap::BAD_BLOCKS);
+        sb.update_sb_csum();
+        let parsed = MdpSuperblock1::from_bytes(&sb.as_bytes()).unwrap();
+        assert_eq!(
+            parsed.array_info.features(),
+            FeatureMap::BITMAP_OFFSET | FeatureMap::BAD_BLOCKS
+        );
+
+        sb.array_info.feature_map = 1 << 20;
+        sb.update_sb_csum();
+        assert!(MdpSuperblock1::from_bytes(&sb.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_device_roles_and_array_state() {
+        for role in [
+            DeviceRole::Active(0),
+            DeviceRole::Active(3),
+            DeviceRole::Spare,
+            DeviceRole::Faulty,
+            DeviceRole::Journal,
+        ] {
+            assert_eq!(DeviceRole::from(u16::from(role)), role);
+        }
+        assert_eq!(DeviceRole::from(0xff80), DeviceRole::Spare);
+
+        let config = config(ArrayLevel::Raid1, 2);
+        let mut sb = member(&config, 0x800, 1).unwrap();
+        assert_eq!(sb.role(), DeviceRole::Active(1));
+        assert_eq!(sb.array_state(), "AA");
+
+        sb.set_role(0, DeviceRole::Faulty);
+        assert_eq!(sb.array_state(), ".A");
+
+        // a replacement for slot 1 shares its role with the original
+        sb.set_role(0, DeviceRole::Active(0));
+        sb.set_role(2, DeviceRole::Active(1));
+        sb.array_info.set_features(FeatureMap::REPLACEMENT);
+        assert_eq!(sb.array_state(), "AR");
+
+        sb.update_sb_csum();
+        let parsed = MdpSuperblock1::from_bytes(&sb.as_bytes()).unwrap();
+        assert_eq!(parsed.role_of(2), DeviceRole::Active(1));
+
+        // shrinking from 3 to 2 disks, raid_disks is already the new count
+        // and the slot being removed is still listed
+        sb.set_role(2, DeviceRole::Active(2));
+        sb.array_info.set_features(FeatureMap::RESHAPE_ACTIVE);
+        sb.feature_bit4.delta_disks = -1i32 as u32;
+        assert_eq!(sb.array_state(), "AAA");
+        // growing lists only the new count
+        sb.feature_bit4.delta_disks = 1;
+        assert_eq!(sb.array_state(), "AA");
+    }
+
+    #[test]
+    fn test_reshape_state() {
+        let config = config(ArrayLevel::Raid1, 2);
+        let mut sb = member(&config, 0x800, 0).unwrap();
+        assert_eq!(sb.reshape(), None);
+
+        // growing a 3 disk raid5 by one disk, 1024 sectors in
+        sb.feature_bit4 = FeatureBit4 {
+            new_level: 5,
+            reshape_position: 1024,
+            delta_disks: 1,
+            new_layout: 2,
+            new_chunk: 1024,
+            new_offset: -2048i32 as u32,
+        };
+        sb.array_info
+            .set_features(FeatureMap::RESHAPE_ACTIVE | FeatureMap::NEW_OFFSET);
+        sb.update_sb_csum();
+        let parsed = MdpSuperblock1::from_bytes(&sb.as_bytes()).unwrap();
+        let reshape = parsed.reshape().unwrap();
+        assert_eq!(
+            reshape,
+            ReshapeState {
+                new_level: 5,
+                reshape_position: 1024,
+                delta_disks: 1,
+                new_layout: 2,
+                new_chunk: 1024,
+                new_offset: Some(-2048),
+                backwards: false,
+            }
+        );
+        assert_eq!(reshape.old_raid_disks(4), 3);
+
+        // shrinking runs backwards
+        sb.feature_bit4.delta_disks = -1i32 as u32;
+        sb.array_info
+            .set_features(FeatureMap::RESHAPE_ACTIVE | FeatureMap::RESHAPE_BACKWARDS);
+        let reshape = sb.reshape().unwrap();
+        assert_eq!(reshape.delta_disks, -1);
+        assert_eq!(reshape.new_offset, None);
+        assert!(reshape.backwards);
+        assert_eq!(reshape.old_raid_disks(2), 3);
+    }
+
+    #[test]
+    fn test_superblock_layout() {
+        let config = config(ArrayLevel::Raid1, 2);
+        let mut sb = member(&config, 0x800, 3).unwrap();
+        sb.feature_bit4.reshape_position = 0x0102030405060708;
+        sb.feature_bit4.new_offset = -8i32 as u32;
+        sb.device_info.bblog_shift = 3;
+        sb.array_state_info.events = 0x1122334455667788;
+        let bytes = sb.as_bytes();
+
+        // offsets from struct mdp_superblock_1 in md_p.h, all little-endian
+        let le32 = |off: usize| u32::from_le_bytes(*array_ref!(bytes, off, 4));
+        let le64 = |off: usize| u64::from_le_bytes(*array_ref!(bytes, off, 8));
+        assert_eq!(le32(0), 0xa92b4efc);
+        assert_eq!(le32(4), 1);
+        assert_eq!(&bytes[32..50], b"testhost:testarray");
+        assert_eq!(le32(72), 1); // level
+        assert_eq!(le64(80), sb.array_info.size);
+        assert_eq!(le32(92), 2); // raid_disks
+        assert_eq!(le64(104), 0x0102030405060708);
+        assert_eq!(le32(124), -8i32 as u32);
+        assert_eq!(le64(128), 0x800); // data_offset
+        assert_eq!(le64(144), 8); // super_offset
+        assert_eq!(le32(160), 3); // dev_number
+        assert_eq!(&bytes[168..184], &sb.device_info.device_uuid);
+        assert_eq!(bytes[185], 3); // bblog_shift
+        assert_eq!(u16::from_le_bytes([bytes[186], bytes[187]]), 8);
+        assert_eq!(le64(200), 0x1122334455667788);
+        assert_eq!(le32(220), 0x80); // max_dev
+        assert_eq!(bytes.len(), 256 + 0x80 * 2);
+        assert_eq!(&bytes[256..260], &[0, 0, 1, 0]); // dev_roles
+
+        let parsed = MdpSuperblock1::from_bytes_lenient(&bytes).unwrap();
+        assert_eq!(parsed.array_info, sb.array_info);
+        assert_eq!(parsed.feature_bit4, sb.feature_bit4);
+        assert_eq!(parsed.device_info, sb.device_info);
+        assert_eq!(parsed.array_state_info, sb.array_state_info);
+    }
+
+    #[test]
+    fn test_new_rejects_bad_input() {
+        let new = |host: &str, level: ArrayLevel| {
+            let config = ArrayConfig {
+                host: host.to_string(),
+                ..config(level, 2)
+            };
+            member(&config, 0x800, 0)
+        };
+        // "host:name" is 33 bytes, one more than set_name holds
+        let host = "a".repeat(23);
+        assert!(matches!(
+            new(&host, ArrayLevel::Raid1),
+            Err(MdError::NameTooLong { max: 32, .. })
+        ));
+        assert!(new(&host[1..], ArrayLevel::Raid1).is_ok());
+        assert!(matches!(
+            new("testhost", ArrayLevel::Raid6),
+            Err(MdError::TooFewDevices {
+                level: ArrayLevel::Raid6,
+                min: 4,
+                found: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_new_all_levels() {
+        // 10MiB members, 18432 sectors of data after a 1MiB offset, 16384 after 2MiB
+        let new = |level: ArrayLevel| {
+            let config = config(level, 4);
+            let chunk = level.chunk_sectors(None).unwrap();
+            let data_offset = config
+                .version
+                .data_offset(&[config.size_bytes / 512], chunk, None)
+                .unwrap();
+            member(&config, data_offset, 0).unwrap().array_info
+        };
+        let cases = [
+            (ArrayLevel::Linear, 0, 0, 18432 * 4),
+            (ArrayLevel::Raid0, 1024, 0, 16384 * 4),
+            (ArrayLevel::Raid1, 0, 0, 18432),
+            (ArrayLevel::Raid4, 1024, 0, 16384 * 3),
+            (ArrayLevel::Raid5, 1024, 2, 16384 * 3),
+            (ArrayLevel::Raid6, 1024, 2, 16384 * 2),
+            (ArrayLevel::Raid10, 1024, 0x102, 16384 * 2),
+            (ArrayLevel::Multipath, 0, 0, 18432),
+        ];
+        for (level, chunksize, layout, array_size) in cases {
+            let info = new(level);
+            assert_eq!(info.level as i32, level as i32);
+            assert_eq!(info.chunksize, chunksize, "{level}");
+            assert_eq!(info.layout, layout, "{level}");
+            assert_eq!(info.array_size(), Some(array_size), "{level}");
+        }
+    }
+
+    #[test]
+    fn test_array_size_corrupt_raid_disks() {
+        let config = config(ArrayLevel::Raid6, 4);
+        let mut info = member(&config, 0x800, 0).unwrap().array_info;
+        for raid_disks in [0, 1, 3] {
+            info.raid_disks = raid_disks;
+            assert_eq!(info.array_size(), None, "{raid_disks}");
+        }
+        info.level = ArrayLevel::Raid5.into();
+        assert_eq!(info.array_size(), Some(info.size * 2));
+        info.raid_disks = 1;
+        assert_eq!(info.array_size(), None);
+        info.level = ArrayLevel::Raid0.into();
+        info.size = u64::MAX;
+        info.raid_disks = 2;
+        assert_eq!(info.array_size(), None);
+    }
+
+    #[test]
+    fn test_parity_layouts() {
+        for layout in ArrayLayout::ALL {
+            assert_eq!(ArrayLayout::from_raw(layout.into()), Some(layout));
+            assert_eq!(layout.name().parse(), Ok(layout));
+            assert!(layout.is_valid_for(ArrayLevel::Raid6));
+        }
+        assert_eq!(ArrayLayout::from_raw(6), None);
+        assert_eq!("ls".parse(), Ok(ArrayLayout::LeftSymmetric));
+        assert!("left".parse::<ArrayLayout>().is_err());
+
+        assert!(ArrayLayout::ParityFirst.is_valid_for(ArrayLevel::Raid5));
+        assert!(!ArrayLayout::DdfNRestart.is_valid_for(ArrayLevel::Raid5));
+        assert!(!ArrayLayout::LeftSymmetric6.is_valid_for(ArrayLevel::Raid5));
+        assert!(!ArrayLayout::LeftSymmetric.is_valid_for(ArrayLevel::Raid4));
+        assert!(!ArrayLayout::LeftSymmetric.is_valid_for(ArrayLevel::Raid1));
+
+        let new = |level: ArrayLevel, layout: u32| {
+            let config = ArrayConfig {
+                layout: Some(layout),
+                ..config(level, 4)
+            };
+            member(&config, 0x1000, 0)
+        };
+        let sb = new(ArrayLevel::Raid6, ArrayLayout::DdfNContinue.into()).unwrap();
+        assert_eq!(sb.array_info.layout, 10);
+        assert!(matches!(
+            new(ArrayLevel::Raid5, ArrayLayout::DdfNContinue.into()),
+            Err(MdError::InvalidLayout { layout: 10, .. })
+        ));
+        assert!(matches!(
+            new(ArrayLevel::Raid5, 7),
+            Err(MdError::InvalidLayout { layout: 7, .. })
+        ));
+        assert!(new(ArrayLevel::Raid1, 2).is_err());
+    }
+
+    #[test]
+    fn test_data_offset() {
+        let v1_2 = SuperblockVersion::V1_2;
+        // the r1_d1 image, 10MiB
+        assert_eq!(v1_2.data_offset(&[20480], 0, None).unwrap(), 2048);
+        // four 512KiB chunks
+        assert_eq!(v1_2.data_offset(&[20480], 1024, None).unwrap(), 4096);
+        // capped at 128MiB
+        let tib = 2 * 1024 * 1024 * 1024;
+        assert_eq!(v1_2.data_offset(&[tib], 1024, None).unwrap(), 262144);
+        assert_eq!(
+            SuperblockVersion::V1_1
+                .data_offset(&[tib], 0, None)
+                .unwrap(),
+            262144
+        );
+        assert_eq!(
+            SuperblockVersion::V1_0
+                .data_offset(&[tib], 1024, None)
+                .unwrap(),
+            0
+        );
+
+        // superblock at 8, bitmap space at 16..24, bad block log at 24..32
+        assert_eq!(v1_2.data_offset(&[20480], 0, Some(32)).unwrap(), 32);
+        assert_eq!(
+            SuperblockVersion::V1_0
+                .data_offset(&[20480], 0, Some(8))
+                .unwrap(),
+            8
+        );
+        for (version, requested) in [
+            (v1_2, 24),
+            (v1_2, 2049),
+            (v1_2, 20480),
+            (SuperblockVersion::V1_0, 20480 - 16),
+        ] {
+            assert!(matches!(
+                version.data_offset(&[20480], 0, Some(requested)),
+                Err(MdError::InvalidDataOffset { .. })
+            ));
+        }
+
+        // over 8GiB a member reserves 64KiB for the bitmap, its bad block log
+        // ends at 8 + 8 + 128 + 8 sectors
+        let gib9 = 9 * 1024 * 1024 * 2;
+        assert_eq!(v1_2.data_offset(&[20480, gib9], 0, Some(152)).unwrap(), 152);
+        assert!(matches!(
+            v1_2.data_offset(&[20480, gib9], 0, Some(32)),
+            Err(MdError::InvalidDataOffset { min: 152, .. })
+        ));
+        // and the smallest member still has to leave room for data
+        assert!(matches!(
+            v1_2.data_offset(&[20480, gib9], 0, Some(20480)),
+            Err(MdError::InvalidDataOffset { .. })
+        ));
+    }
+
+    #[test]
+    fn test_chunk_size() {
+        // 16484 sectors of data after 2MiB, not a multiple of any chunk above 4KiB
+        let new = |level: ArrayLevel, chunk_kib: Option<u32>| {
+            let config = ArrayConfig {
+                chunk_kib,
+                size_bytes: 10 * 1024 * 1024 + 50 * 1024,
+                ..config(level, 4)
+            };
+            member(&config, 0x1000, 0).map(|sb| (sb.array_info.chunksize, sb.array_info.size))
+        };
+        assert_eq!(new(ArrayLevel::Raid5, None).unwrap(), (1024, 16384));
+        assert_eq!(new(ArrayLevel::Raid5, Some(4)).unwrap(), (8, 16480));
+        assert_eq!(new(ArrayLevel::Raid0, Some(64)).unwrap(), (128, 16384));
+        assert_eq!(new(ArrayLevel::Linear, Some(4)).unwrap(), (8, 16480));
+        assert_eq!(new(ArrayLevel::Raid1, None).unwrap(), (0, 16484));
+        for (level, kib) in [
+            (ArrayLevel::Raid5, 2),
+            (ArrayLevel::Raid5, 48),
+            (ArrayLevel::Raid5, 0),
+            (ArrayLevel::Raid1, 64),
+            // larger than the members
+            (ArrayLevel::Raid6, 16 * 1024),
+            // too large for the sector count to fit in chunksize
+            (ArrayLevel::Raid0, 1 << 31),
+        ] {
+            assert!(
+                matches!(new(level, Some(kib)), Err(MdError::InvalidChunkSize { .. })),
+                "{level} {kib}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_can_run_degraded() {
+        let present = |slots: &str| slots.chars().map(|c| c == 'U').collect::<Vec<_>>();
+        assert!(ArrayLevel::Raid1.can_run(0, &present("U_")));
+        assert!(!ArrayLevel::Raid1.can_run(0, &present("__")));
+        assert!(!ArrayLevel::Raid0.can_run(0, &present("UU_")));
+        assert!(ArrayLevel::Raid5.can_run(2, &present("U_U")));
+        assert!(!ArrayLevel::Raid5.can_run(2, &present("U__")));
+        assert!(ArrayLevel::Raid6.can_run(2, &present("U_U_")));
+        assert!(!ArrayLevel::Raid6.can_run(2, &present("U___")));
+
+        // n2 over 4 disks mirrors slots 0+1 and 2+3
+        let n2 = Raid10Layout::near(2).into();
+        assert!(ArrayLevel::Raid10.can_run(n2, &present("U__U")));
+        assert!(!ArrayLevel::Raid10.can_run(n2, &present("__UU")));
+        // f2 keeps the second copy of each slot on the next one
+        let f2 = Raid10Layout::far(2).into();
+        assert!(ArrayLevel::Raid10.can_run(f2, &present("U_U_")));
+        assert!(!ArrayLevel::Raid10.can_run(f2, &present("U__U_")));
+    }
+
+    #[test]
+    fn test_raid10_layout() {
+        assert_eq!(u32::from(Raid10Layout::near(2)), 0x102);
+        assert_eq!(u32::from(Raid10Layout::far(2)), 0x201);
+        assert_eq!(u32::from(Raid10Layout::offset(2)), 0x10201);
+        for raw in [0x102, 0x201, 0x10201, 0x103, 0x202] {
+            assert_eq!(u32::from(Raid10Layout::from(raw)), raw);
+        }
+        assert_eq!(Raid10Layout::from(0x202).copies(), 4);
+
+        assert_eq!("n2".parse(), Ok(Raid10Layout::near(2)));
+        assert_eq!("f3".parse(), Ok(Raid10Layout::far(3)));
+        assert_eq!("o2".parse(), Ok(Raid10Layout::offset(2)));
+        assert_eq!("n2f2".parse::<Raid10Layout>().map(u32::from), Ok(0x202));
+        for bad in ["", "x2", "n", "n0", "2", "é2"] {
+            assert!(bad.parse::<Raid10Layout>().is_err(), "{bad}");
+        }
+        for layout in ["n2", "f2", "o3", "n2f2", "n1"] {
+            assert_eq!(layout.parse::<Raid10Layout>().unwrap().to_string(), layout);
+        }
+
+        // 3 copies don't fit on 2 devices
+        let new = |layout: Raid10Layout| {
+            let config = ArrayConfig {
+                layout: Some(layout.into()),
+                ..config(ArrayLevel::Raid10, 2)
+            };
+            member(&config, 0x1000, 0)
+        };
+        let sb = new(Raid10Layout::far(2)).unwrap();
+        assert_eq!(sb.array_info.layout, 0x201);
+        assert!(matches!(
+            new(Raid10Layout::near(3)),
+            Err(MdError::InvalidLayout {
+                level: ArrayLevel::Raid10,
+                layout: 0x103
+            })
+        ));
+    }
+
+    #[test]
+    fn test_checksum_is_verified() {
+        let config = config(ArrayLevel::Raid1, 2);
+        let sb = member(&config, 0x800, 0).unwrap();
+        let expected = sb.array_state_info.sb_csum;
+
+        let mut bytes = sb.as_bytes();
+        // flip a bit in the array name
+        bytes[32] ^= 1;
+        let err = MdpSuperblock1::from_bytes(&bytes).unwrap_err();
+        assert!(matches!(
+            err,
+            MdError::ChecksumMismatch { expected: e, computed: c }
+                if e == expected && c == expected.wrapping_add(1)
+        ));
+
+        let parsed = MdpSuperblock1::from_bytes_lenient(&bytes).unwrap();
+        assert!(parsed.verify_sb_csum().is_err());
+
+        // not md at all is a different error
+        let err = MdpSuperblock1::from_bytes(&[0; 4096]).unwrap_err();
+        assert!(matches!(
+            err,
+            MdError::BadMagic {
+                what: "superblock",
+                found: 0
+            }
+        ));
+    }
+
+    #[test]
+    fn test_internal_bitmap_location() {
+        for (version, bitmap_offset, room) in [
+            (SuperblockVersion::V1_2, 8, 8),
+            (SuperblockVersion::V1_1, 8, 8),
+            (SuperblockVersion::V1_0, -16, 8),
+        ] {
+            let config = ArrayConfig {
+                version,
+                internal_bitmap: true,
+                ..config(ArrayLevel::Raid1, 2)
+            };
+            let data_offset = version
+                .data_offset(&[config.size_bytes / 512], 0, None)
+                .unwrap();
+            let sb = member(&config, data_offset, 0).unwrap();
+            let parsed = MdpSuperblock1::from_bytes(&sb.as_bytes()).unwrap();
+            assert!(parsed
+                .array_info
+                .features()
+                .contains(FeatureMap::BITMAP_OFFSET));
+            assert_eq!(parsed.array_info.bitmap_offset(), Some(bitmap_offset));
+            assert_eq!(parsed.bitmap_room(), Some(room));
+
+            let super_offset = sb.device_info.super_offset as i64;
+            assert_eq!(
+                sb.bitmap_location(),
+                Some(((super_offset + bitmap_offset as i64) * 512) as u64)
+            );
+            // the bitmap must not overlap the data
+            let data_end = (sb.device_info.data_offset + sb.device_info.data_size) * 512;
+            let bitmap_start = sb.bitmap_location().unwrap();
+            if version == SuperblockVersion::V1_0 {
+                assert_eq!(bitmap_start, data_end);
+            } else {
+                assert!(bitmap_start + room * 512 <= sb.device_info.data_offset * 512);
+            }
+
+            let bitmap = sb.new_bitmap().unwrap();
+            let size = sb.array_info.size;
+            assert_eq!(bitmap.sync_size, size);
+            assert_eq!(bitmap.uuid(), sb.array_info.uuid());
+        }
+    }
 }
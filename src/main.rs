@@ -1,75 +1,296 @@
 use anyhow::{bail, Result};
-use chrono::Utc;
-use device_mapper::{ArrayLevel, DeviceInfo, MdpSuperblock1};
-use std::fs::OpenOptions;
-use std::io::prelude::*;
+use chrono::Local;
+use clap::{Parser, Subcommand};
+use device_mapper::{
+    assemble, block, control, create, create::ArrayBuilder, zero, ArrayLayout, ArrayLevel,
+    BitmapSuperblock, Raid10Layout, Superblock, SuperblockVersion,
+};
 use std::path::Path;
-use uuid::Uuid;
 
-mod assemble;
-mod block;
-
-fn main() {
-    //_create_example_array();
-    assemble::assemble_array(&vec!["/dev/loop19", "/dev/loop20", "/dev/loop21"], 99).unwrap();
+#[derive(Parser)]
+#[command(name = "md", about = "Create, assemble and inspect Linux md arrays")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
 }
 
-fn create_array(level: ArrayLevel, backing_devs: &[&str]) -> Result<()> {
-    let host = "worklaptop";
-    let array_name = "0";
-    let array_uuid = Some(Uuid::new_v4());
+#[derive(Subcommand)]
+enum Command {
+    /// Write superblocks for a new array to the member devices
+    Create {
+        /// linear, 0, 1, 4, 5, 6 or 10, optionally prefixed with "raid"
+        #[arg(short, long, value_parser = str::parse::<ArrayLevel>)]
+        level: ArrayLevel,
+        /// raid4/5/6: mdadm's layout names like left-symmetric (the default) or parity-first,
+        /// raid10: n<copies>, f<copies> or o<copies> (default n2)
+        #[arg(short = 'p', long)]
+        layout: Option<String>,
+        /// Chunk size in KiB, a power of two of at least 4 (default 512)
+        #[arg(short, long)]
+        chunk: Option<u32>,
+        /// Start of the data area on each device in KiB, instead of leaving room for
+        /// the metadata and reshapes like mdadm
+        #[arg(long)]
+        data_offset: Option<u64>,
+        /// Space to use on each device in KiB, defaults to all of the smallest device
+        #[arg(short = 'z', long)]
+        size: Option<u64>,
+        /// Array name, stored as <homehost>:<name>
+        #[arg(short = 'N', long)]
+        name: String,
+        /// Defaults to the hostname
+        #[arg(long)]
+        homehost: Option<String>,
+        /// Superblock version: 1.0, 1.1 or 1.2
+        #[arg(short = 'e', long, default_value = "1.2", value_parser = parse_metadata)]
+        metadata: SuperblockVersion,
+        /// Add an internal write-intent bitmap
+        #[arg(long)]
+        internal_bitmap: bool,
+        /// Overwrite devices that already hold md metadata or a filesystem
+        #[arg(short, long)]
+        force: bool,
+        /// Assemble the new array as md<MINOR>
+        #[arg(short, long)]
+        minor: Option<u32>,
+        #[arg(required = true)]
+        devices: Vec<String>,
+    },
+    /// Start an array from the superblocks on its member devices
+    Assemble {
+        /// Assemble as md<MINOR>
+        #[arg(short, long)]
+        minor: u32,
+        /// Start an array that lost too many members at once, by raising the
+        /// event count of as few stale members as it needs, most recent first
+        #[arg(short, long)]
+        force: bool,
+        /// Start the array even if members are missing, as long as no data is lost
+        #[arg(short = 'R', long)]
+        run: bool,
+        #[arg(required = true)]
+        devices: Vec<String>,
+    },
+    /// Print the superblock of member devices
+    Examine {
+        /// One JSON object per device, for v1.x superblocks (needs the serde feature)
+        #[arg(long)]
+        json: bool,
+        #[arg(required = true)]
+        devices: Vec<String>,
+    },
+    /// Print the state of a running array
+    Detail {
+        #[arg(short, long)]
+        minor: u32,
+    },
+    /// Stop a running array
+    Stop {
+        #[arg(short, long)]
+        minor: u32,
+    },
+    /// Wipe md superblocks, bitmaps and bad block logs from devices that are not
+    /// part of a running array
+    ZeroSuperblock {
+        #[arg(required = true)]
+        devices: Vec<String>,
+    },
+}
 
-    let mut min_disk_size = u64::MAX;
-    for dev in backing_devs {
-        let path = Path::new(dev);
-        let device_size = block::get_size(&path)?;
-        min_disk_size = min_disk_size.min(device_size);
-    }
-    if min_disk_size < 10_240 {
-        // not sure why, but `mdadm --examine` will say
-        // mdadm: No md superblock detected on my-device-1.
-        // if there are less than 8KiB after the header
-        // this is not a problem for normal block devices, only artificial cases using files
-        bail!("Smallest block device is smaller than minimum acceptable (10KiB)")
+fn parse_metadata(s: &str) -> Result<SuperblockVersion, String> {
+    match s {
+        "1.0" => Ok(SuperblockVersion::V1_0),
+        "1.1" => Ok(SuperblockVersion::V1_1),
+        "1.2" | "1" | "default" => Ok(SuperblockVersion::V1_2),
+        _ => Err(format!("unsupported metadata version {s}")),
     }
-    let data_offset = match level {
-        ArrayLevel::Raid1 => 0x800,  // why 1MB on top of superblock?
-        ArrayLevel::Raid5 => 0x1000, // why 2MB on top of superblock?
-        _ => todo!("unsupported"),
-    };
+}
 
-    let now = Utc::now();
-    for (i, dev) in backing_devs.iter().enumerate() {
-        let block_size = 512;
-        let path = Path::new(dev);
-        let device_size = block::get_size(&path)?;
+fn parse_layout(level: ArrayLevel, layout: &str) -> Result<u32> {
+    match level {
+        ArrayLevel::Raid4 | ArrayLevel::Raid5 | ArrayLevel::Raid6 => Ok(layout
+            .parse::<ArrayLayout>()
+            .map_err(anyhow::Error::msg)?
+            .into()),
+        ArrayLevel::Raid10 => Ok(layout
+            .parse::<Raid10Layout>()
+            .map_err(anyhow::Error::msg)?
+            .into()),
+        _ => bail!("--layout is not supported for {level}"),
+    }
+}
 
-        let device_info = DeviceInfo::new(device_size, block_size, data_offset, i as u32, None);
-        let sb = MdpSuperblock1::new(
-            host,
-            array_name,
-            array_uuid,
-            now,
-            min_disk_size,
-            block_size,
-            backing_devs.len() as u32,
-            device_info,
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Create {
             level,
-        )?;
+            layout,
+            chunk,
+            data_offset,
+            size,
+            name,
+            homehost,
+            metadata,
+            internal_bitmap,
+            force,
+            minor,
+            devices,
+        } => {
+            let mut builder = ArrayBuilder::new(level, &name)
+                .metadata(metadata)
+                .internal_bitmap(internal_bitmap)
+                .force(force);
+            if let Some(homehost) = homehost {
+                builder = builder.homehost(&homehost);
+            }
+            if let Some(layout) = layout {
+                builder = builder.layout(parse_layout(level, &layout)?);
+            }
+            if let Some(chunk) = chunk {
+                builder = builder.chunk_kib(chunk);
+            }
+            if let Some(kib) = data_offset {
+                builder = builder.data_offset(kib * 2);
+            }
+            if let Some(kib) = size {
+                builder = builder.component_size(kib * 2);
+            }
+            let devices: Vec<&str> = devices.iter().map(String::as_str).collect();
+            let superblocks = builder.create(&devices)?;
+            for (dev, sb) in devices.iter().zip(&superblocks) {
+                // same warning as mdadm, mixing sizes is fine but should be deliberate
+                let avail = sb.device_info.data_size;
+                let unused = avail.saturating_sub(sb.array_info.size);
+                if unused * 100 > avail {
+                    eprintln!(
+                        "md: {dev} exceeds the component size by more than 1%, {unused} sectors are unused"
+                    );
+                }
+            }
+            if let Some(minor) = minor {
+                assemble::assemble_array(&devices, minor, false, false)?;
+            }
+        }
+        Command::Assemble {
+            minor,
+            force,
+            run,
+            devices,
+        } => {
+            let devices: Vec<&str> = devices.iter().map(String::as_str).collect();
+            let report = assemble::assemble_array(&devices, minor, force, run)?;
+            for member in &report.forced {
+                eprintln!(
+                    "md: forcing event count in {} ({:?}) from {} up to {}",
+                    member.path, member.role, member.events, report.events
+                );
+            }
+            for member in &report.excluded {
+                eprintln!(
+                    "md: excluding {} ({:?}) with event count {}, the array is at {}",
+                    member.path, member.role, member.events, report.events
+                );
+            }
+            if !report.missing.is_empty() {
+                let state = if report.started {
+                    "started degraded".to_string()
+                } else {
+                    format!(
+                        "left inactive. To start it degraded, run `md stop --minor {minor}` \
+                         and assemble again with --run"
+                    )
+                };
+                eprintln!(
+                    "md: md{minor} is missing slots {:?}, {state}",
+                    report.missing
+                );
+            }
+        }
+        Command::Examine { json, devices } => {
+            for dev in &devices {
+                if json {
+                    examine_json(dev)?;
+                } else {
+                    examine(dev)?;
+                }
+            }
+        }
+        Command::Detail { minor } => detail(minor)?,
+        Command::Stop { minor } => control::stop(minor)?,
+        Command::ZeroSuperblock { devices } => {
+            for dev in &devices {
+                zero::zero_superblock(dev)?;
+            }
+        }
+    }
+    Ok(())
+}
 
-        let mut file = OpenOptions::new().write(true).open(dev)?;
-        file.seek(std::io::SeekFrom::Start(0x1000))?;
-        file.write_all(&sb.as_bytes())?;
+fn examine(dev: &str) -> Result<()> {
+    let sb = Superblock::probe(dev)?;
+    println!("{dev}:");
+    let sb = match sb {
+        Superblock::V1(_, sb) => sb,
+        Superblock::V0_90(sb) => {
+            print!("{}", sb.examine(Local));
+            return Ok(());
+        }
+    };
+    let device_size = block::get_size(Path::new(dev))? / 512;
+    let host = create::hostname()?;
+    let bitmap = match sb.bitmap_location() {
+        Some(location) => Some(BitmapSuperblock::from_file(dev, location)?),
+        None => None,
+    };
+    let mut examine = sb.examine(device_size, Some(&host), Local);
+    if let Some(bitmap) = &bitmap {
+        examine = examine.with_bitmap(bitmap);
     }
+    print!("{examine}");
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+fn examine_json(dev: &str) -> Result<()> {
+    let Superblock::V1(version, sb) = Superblock::probe(dev)? else {
+        bail!("{dev}: --json only supports v1.x superblocks");
+    };
+    let out = serde_json::json!({
+        "device": dev,
+        "version": format!("1.{}", version.minor_version()),
+        "superblock": sb,
+    });
+    println!("{out}");
     Ok(())
 }
 
-fn _create_example_array() {
-    create_array(
-        ArrayLevel::Raid5,
-        &vec!["my-device-1", "my-device-2", "my-device-3"],
-        //&vec!["my-device-1", "my-device-2"],
-    )
-    .unwrap();
-    //create_array(ArrayLevel::Raid1, &vec!["my-device-2", "/dev/loop1"]).unwrap();
+#[cfg(not(feature = "serde"))]
+fn examine_json(_dev: &str) -> Result<()> {
+    bail!("md was built without JSON support, rebuild with --features serde")
+}
+
+fn detail(minor: u32) -> Result<()> {
+    let info = control::array_info(minor)?;
+    let level = match ArrayLevel::from_raw(info.level) {
+        Some(level) => level.to_string(),
+        None => format!("unknown ({})", info.level),
+    };
+    println!("/dev/md{minor}:");
+    println!(
+        "           Version : {}.{}",
+        info.major_version, info.minor_version
+    );
+    println!("        Raid Level : {level}");
+    println!("     Used Dev Size : {} KiB", info.size);
+    println!("      Raid Devices : {}", info.raid_disks);
+    println!("     Total Devices : {}", info.nr_disks);
+    println!("    Active Devices : {}", info.active_disks);
+    println!("   Working Devices : {}", info.working_disks);
+    println!("    Failed Devices : {}", info.failed_disks);
+    println!("     Spare Devices : {}", info.spare_disks);
+    if info.chunk_size != 0 {
+        println!("        Chunk Size : {}K", info.chunk_size / 1024);
+    }
+    Ok(())
 }
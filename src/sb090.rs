@@ -0,0 +1,516 @@
+//! Legacy v0.90 superblock.
+//!
+//! Unlike v1.x, 0.90 superblocks are written in the host's native byte order,
+//! so both little and big endian layouts are parsed, and written back in the
+//! order they were read in.
+use crate::{ioctl, DeviceRole, MdError, ReshapeState, Result};
+use chrono::{DateTime, NaiveDateTime};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use uuid::Uuid;
+
+/// Size of the superblock, MD_SB_BYTES
+pub const MD_SB_BYTES: usize = 4096;
+/// Number of disk descriptors in the superblock, MD_SB_DISKS
+pub const MD_SB_DISKS: usize = 27;
+/// The superblock lives in the last 64KiB-aligned 64KiB of the device, MD_RESERVED_SECTORS
+pub(crate) const MD_RESERVED_SECTORS: u64 = 64 * 1024 / 512;
+/// State bits, MD_SB_CLEAN and MD_SB_BITMAP_PRESENT (a bitmap follows the superblock)
+pub(crate) const MD_SB_CLEAN: u32 = 0;
+pub(crate) const MD_SB_BITMAP_PRESENT: u32 = 8;
+
+// word offsets of each section, from md_p.h
+const MD_SB_GENERIC_STATE_OFFSET: usize = 32;
+const MD_SB_PERSONALITY_OFFSET: usize = 64;
+const MD_SB_DISKS_OFFSET: usize = 128;
+const MD_SB_DESCRIPTOR_WORDS: usize = 32;
+const MD_SB_DESCRIPTOR_OFFSET: usize = 992;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    fn get_u32(&self, buf: &[u8], word: usize) -> u32 {
+        let bytes = [
+            buf[word * 4],
+            buf[word * 4 + 1],
+            buf[word * 4 + 2],
+            buf[word * 4 + 3],
+        ];
+        match self {
+            ByteOrder::Little => u32::from_le_bytes(bytes),
+            ByteOrder::Big => u32::from_be_bytes(bytes),
+        }
+    }
+    fn put_u32(&self, buf: &mut [u8], word: usize, value: u32) {
+        let bytes = match self {
+            ByteOrder::Little => value.to_le_bytes(),
+            ByteOrder::Big => value.to_be_bytes(),
+        };
+        buf[word * 4..word * 4 + 4].copy_from_slice(&bytes);
+    }
+    // 64 bit values are stored as two native words, ordered so that they read
+    // back as a native u64 (events_lo/events_hi swap places on big endian)
+    fn get_u64(&self, buf: &[u8], word: usize) -> u64 {
+        let hi_lo = (
+            self.get_u32(buf, word) as u64,
+            self.get_u32(buf, word + 1) as u64,
+        );
+        match self {
+            ByteOrder::Little => hi_lo.0 | (hi_lo.1 << 32),
+            ByteOrder::Big => (hi_lo.0 << 32) | hi_lo.1,
+        }
+    }
+    fn put_u64(&self, buf: &mut [u8], word: usize, value: u64) {
+        let (first, second) = match self {
+            ByteOrder::Little => (value as u32, (value >> 32) as u32),
+            ByteOrder::Big => ((value >> 32) as u32, value as u32),
+        };
+        self.put_u32(buf, word, first);
+        self.put_u32(buf, word + 1, second);
+    }
+}
+
+/// mdp_disk_t
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskDescriptor090 {
+    pub number: u32,
+    pub major: u32,
+    pub minor: u32,
+    pub raid_disk: u32,
+    pub state: u32, // MD_DISK_* bits
+}
+
+impl DiskDescriptor090 {
+    fn from_bytes(buf: &[u8], word: usize, order: ByteOrder) -> Self {
+        DiskDescriptor090 {
+            number: order.get_u32(buf, word),
+            major: order.get_u32(buf, word + 1),
+            minor: order.get_u32(buf, word + 2),
+            raid_disk: order.get_u32(buf, word + 3),
+            state: order.get_u32(buf, word + 4),
+        }
+    }
+    fn write_bytes(&self, buf: &mut [u8], word: usize, order: ByteOrder) {
+        order.put_u32(buf, word, self.number);
+        order.put_u32(buf, word + 1, self.major);
+        order.put_u32(buf, word + 2, self.minor);
+        order.put_u32(buf, word + 3, self.raid_disk);
+        order.put_u32(buf, word + 4, self.state);
+    }
+}
+
+/// mdp_super_t
+#[derive(Debug, Clone)]
+pub struct MdpSuperblock090 {
+    pub byte_order: ByteOrder,
+    // Generic constant information
+    pub major_version: u32,
+    pub minor_version: u32,
+    pub patch_version: u32,
+    pub gvalid_words: u32,
+    set_uuid: [u32; 4],
+    pub ctime: u32,    // seconds
+    pub level: u32,    /* -4 (multipath), -1 (linear), 0,1,4,5 */
+    pub size: u32,     // used size of the component devices, in KiB
+    pub nr_disks: u32, // total disks in the raid set
+    pub raid_disks: u32,
+    pub md_minor: u32,
+    pub not_persistent: u32,
+    // Generic state information
+    pub utime: u32,
+    pub state: u32, // MD_SB_CLEAN, MD_SB_ERRORS, ...
+    pub active_disks: u32,
+    pub working_disks: u32,
+    pub failed_disks: u32,
+    pub spare_disks: u32,
+    pub sb_csum: u32,
+    pub events: u64,
+    pub cp_events: u64,
+    pub recovery_cp: u32,
+    // only valid for minor_version > 90
+    pub reshape_position: u64,
+    pub new_level: u32,
+    pub delta_disks: u32,
+    pub new_layout: u32,
+    pub new_chunk: u32,
+    // Personality information
+    pub layout: u32,
+    pub chunk_size: u32, // in bytes
+    pub disks: [DiskDescriptor090; MD_SB_DISKS],
+    pub this_disk: DiskDescriptor090,
+}
+
+impl MdpSuperblock090 {
+    pub(crate) const SUPERBLOCK_MAGIC: u32 = 0xa92b4efc;
+    const MAJOR_VERSION: u32 = 0;
+
+    /// Location of the superblock, in bytes, on a device of the given size.
+    /// Returns None if the device is too small to hold a superblock.
+    pub fn offset(device_size_bytes: u64) -> Option<u64> {
+        // MD_NEW_SIZE_SECTORS
+        let sectors = device_size_bytes / 512;
+        let sb_sector = (sectors & !(MD_RESERVED_SECTORS - 1)).checked_sub(MD_RESERVED_SECTORS)?;
+        Some(sb_sector * 512)
+    }
+
+    /// Parse and verify the checksum, see [`MdpSuperblock1::from_bytes`]
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        let sb = Self::from_bytes_lenient(buf)?;
+        // summed over the raw buffer, reserved words are part of the checksum
+        let computed = csum(buf, sb.byte_order);
+        if sb.sb_csum != computed {
+            return Err(MdError::ChecksumMismatch {
+                expected: sb.sb_csum,
+                computed,
+            });
+        }
+        Ok(sb)
+    }
+
+    /// Parse without verifying the checksum, to inspect damaged superblocks
+    pub fn from_bytes_lenient(buf: &[u8]) -> Result<Self> {
+        if buf.len() < MD_SB_BYTES {
+            return Err(MdError::Truncated {
+                what: "MdpSuperblock090",
+            });
+        }
+        let magic = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        let byte_order = if magic == Self::SUPERBLOCK_MAGIC {
+            ByteOrder::Little
+        } else if magic.swap_bytes() == Self::SUPERBLOCK_MAGIC {
+            ByteOrder::Big
+        } else {
+            return Err(MdError::BadMagic {
+                what: "superblock",
+                found: magic,
+            });
+        };
+        let o = byte_order;
+        let major_version = o.get_u32(buf, 1);
+        if major_version != Self::MAJOR_VERSION {
+            return Err(MdError::UnsupportedVersion {
+                what: "superblock",
+                found: major_version,
+            });
+        }
+
+        let gs = MD_SB_GENERIC_STATE_OFFSET;
+        let mut disks = [DiskDescriptor090::default(); MD_SB_DISKS];
+        for (i, disk) in disks.iter_mut().enumerate() {
+            let word = MD_SB_DISKS_OFFSET + i * MD_SB_DESCRIPTOR_WORDS;
+            *disk = DiskDescriptor090::from_bytes(buf, word, o);
+        }
+
+        Ok(MdpSuperblock090 {
+            byte_order,
+            major_version,
+            minor_version: o.get_u32(buf, 2),
+            patch_version: o.get_u32(buf, 3),
+            gvalid_words: o.get_u32(buf, 4),
+            set_uuid: [
+                o.get_u32(buf, 5),
+                o.get_u32(buf, 13),
+                o.get_u32(buf, 14),
+                o.get_u32(buf, 15),
+            ],
+            ctime: o.get_u32(buf, 6),
+            level: o.get_u32(buf, 7),
+            size: o.get_u32(buf, 8),
+            nr_disks: o.get_u32(buf, 9),
+            raid_disks: o.get_u32(buf, 10),
+            md_minor: o.get_u32(buf, 11),
+            not_persistent: o.get_u32(buf, 12),
+            utime: o.get_u32(buf, gs),
+            state: o.get_u32(buf, gs + 1),
+            active_disks: o.get_u32(buf, gs + 2),
+            working_disks: o.get_u32(buf, gs + 3),
+            failed_disks: o.get_u32(buf, gs + 4),
+            spare_disks: o.get_u32(buf, gs + 5),
+            sb_csum: o.get_u32(buf, gs + 6),
+            events: o.get_u64(buf, gs + 7),
+            cp_events: o.get_u64(buf, gs + 9),
+            recovery_cp: o.get_u32(buf, gs + 11),
+            reshape_position: o.get_u64(buf, gs + 12),
+            new_level: o.get_u32(buf, gs + 14),
+            delta_disks: o.get_u32(buf, gs + 15),
+            new_layout: o.get_u32(buf, gs + 16),
+            new_chunk: o.get_u32(buf, gs + 17),
+            layout: o.get_u32(buf, MD_SB_PERSONALITY_OFFSET),
+            chunk_size: o.get_u32(buf, MD_SB_PERSONALITY_OFFSET + 1),
+            disks,
+            this_disk: DiskDescriptor090::from_bytes(buf, MD_SB_DESCRIPTOR_OFFSET, o),
+        })
+    }
+
+    /// Serializes in `byte_order`; reserved words are written as zero
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let o = self.byte_order;
+        let gs = MD_SB_GENERIC_STATE_OFFSET;
+        let mut buf = vec![0u8; MD_SB_BYTES];
+        o.put_u32(&mut buf, 0, Self::SUPERBLOCK_MAGIC);
+        o.put_u32(&mut buf, 1, self.major_version);
+        o.put_u32(&mut buf, 2, self.minor_version);
+        o.put_u32(&mut buf, 3, self.patch_version);
+        o.put_u32(&mut buf, 4, self.gvalid_words);
+        o.put_u32(&mut buf, 5, self.set_uuid[0]);
+        o.put_u32(&mut buf, 6, self.ctime);
+        o.put_u32(&mut buf, 7, self.level);
+        o.put_u32(&mut buf, 8, self.size);
+        o.put_u32(&mut buf, 9, self.nr_disks);
+        o.put_u32(&mut buf, 10, self.raid_disks);
+        o.put_u32(&mut buf, 11, self.md_minor);
+        o.put_u32(&mut buf, 12, self.not_persistent);
+        o.put_u32(&mut buf, 13, self.set_uuid[1]);
+        o.put_u32(&mut buf, 14, self.set_uuid[2]);
+        o.put_u32(&mut buf, 15, self.set_uuid[3]);
+        o.put_u32(&mut buf, gs, self.utime);
+        o.put_u32(&mut buf, gs + 1, self.state);
+        o.put_u32(&mut buf, gs + 2, self.active_disks);
+        o.put_u32(&mut buf, gs + 3, self.working_disks);
+        o.put_u32(&mut buf, gs + 4, self.failed_disks);
+        o.put_u32(&mut buf, gs + 5, self.spare_disks);
+        o.put_u32(&mut buf, gs + 6, self.sb_csum);
+        o.put_u64(&mut buf, gs + 7, self.events);
+        o.put_u64(&mut buf, gs + 9, self.cp_events);
+        o.put_u32(&mut buf, gs + 11, self.recovery_cp);
+        o.put_u64(&mut buf, gs + 12, self.reshape_position);
+        o.put_u32(&mut buf, gs + 14, self.new_level);
+        o.put_u32(&mut buf, gs + 15, self.delta_disks);
+        o.put_u32(&mut buf, gs + 16, self.new_layout);
+        o.put_u32(&mut buf, gs + 17, self.new_chunk);
+        o.put_u32(&mut buf, MD_SB_PERSONALITY_OFFSET, self.layout);
+        o.put_u32(&mut buf, MD_SB_PERSONALITY_OFFSET + 1, self.chunk_size);
+        for (i, disk) in self.disks.iter().enumerate() {
+            let word = MD_SB_DISKS_OFFSET + i * MD_SB_DESCRIPTOR_WORDS;
+            disk.write_bytes(&mut buf, word, o);
+        }
+        self.this_disk
+            .write_bytes(&mut buf, MD_SB_DESCRIPTOR_OFFSET, o);
+        buf
+    }
+
+    /// Sum of all native words with the checksum set to 0, folded to 32 bits
+    pub fn calculate_sb_csum(&self) -> u32 {
+        csum(&self.as_bytes(), self.byte_order)
+    }
+
+    pub fn from_file(path: &str, offset: u64) -> Result<Self> {
+        Self::from_bytes(&Self::read_at(path, offset)?)
+    }
+    pub fn from_file_lenient(path: &str, offset: u64) -> Result<Self> {
+        Self::from_bytes_lenient(&Self::read_at(path, offset)?)
+    }
+    fn read_at(path: &str, offset: u64) -> Result<[u8; MD_SB_BYTES]> {
+        let mut file = File::open(path)?;
+
+        file.seek(SeekFrom::Start(offset))?;
+        let mut buf = [0; MD_SB_BYTES];
+        file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Read the superblock from the end of the device
+    pub fn probe(path: &str) -> Result<Self> {
+        let mut file = File::open(path)?;
+        let device_size = file.seek(SeekFrom::End(0))?;
+        let Some(offset) = Self::offset(device_size) else {
+            // too small to hold a 0.90 superblock
+            return Err(MdError::NoSuperblock {
+                path: path.to_string(),
+            });
+        };
+        Self::from_file(path, offset)
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        // mdadm prints each word as %08x
+        let mut bytes = [0u8; 16];
+        for (chunk, word) in bytes.chunks_exact_mut(4).zip(self.set_uuid) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        Uuid::from_bytes(bytes)
+    }
+    pub fn creation(&self) -> NaiveDateTime {
+        DateTime::from_timestamp(self.ctime as i64, 0)
+            .unwrap()
+            .naive_local()
+    }
+    pub fn update_time(&self) -> NaiveDateTime {
+        DateTime::from_timestamp(self.utime as i64, 0)
+            .unwrap()
+            .naive_local()
+    }
+    /// Role of the device this superblock was read from, derived from its
+    /// descriptor state bits
+    pub fn role(&self) -> DeviceRole {
+        let disk = &self.this_disk;
+        if disk.state & (1 << ioctl::MD_DISK_FAULTY) != 0 {
+            DeviceRole::Faulty
+        } else if disk.state & (1 << ioctl::MD_DISK_ACTIVE) != 0 {
+            DeviceRole::Active(disk.raid_disk as u16)
+        } else {
+            DeviceRole::Spare
+        }
+    }
+    /// Reshape progress, 0.90 marks an active reshape by bumping the minor version
+    pub fn reshape(&self) -> Option<ReshapeState> {
+        if self.minor_version <= 90 {
+            return None;
+        }
+        Some(ReshapeState {
+            new_level: self.new_level,
+            reshape_position: self.reshape_position,
+            delta_disks: self.delta_disks as i32,
+            new_layout: self.new_layout,
+            new_chunk: self.new_chunk / 512,
+            new_offset: None,
+            backwards: false,
+        })
+    }
+}
+
+/// Sum of all words in `byte_order` with the checksum word taken as 0, folded to 32 bits
+fn csum(buf: &[u8], byte_order: ByteOrder) -> u32 {
+    let csum_word = MD_SB_GENERIC_STATE_OFFSET + 6;
+    let csum: u64 = (0..MD_SB_BYTES / 4)
+        .filter(|&word| word != csum_word)
+        .map(|word| byte_order.get_u32(buf, word) as u64)
+        .sum();
+    ((csum & 0xffffffff) + (csum >> 32)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example_superblock(byte_order: ByteOrder) -> MdpSuperblock090 {
+        let mut disks = [DiskDescriptor090::default(); MD_SB_DISKS];
+        for (i, disk) in disks.iter_mut().take(2).enumerate() {
+            *disk = DiskDescriptor090 {
+                number: i as u32,
+                major: 7,
+                minor: i as u32,
+                raid_disk: i as u32,
+                state: (1 << crate::ioctl::MD_DISK_ACTIVE) | (1 << crate::ioctl::MD_DISK_SYNC),
+            };
+        }
+        let mut sb = MdpSuperblock090 {
+            byte_order,
+            major_version: 0,
+            minor_version: 90,
+            patch_version: 0,
+            gvalid_words: 0,
+            set_uuid: [0x24d684dd, 0xbc6760fc, 0xa5d3a49f, 0x592b1b42],
+            ctime: 0x66bb28b3,
+            level: 1,
+            size: 9216,
+            nr_disks: 2,
+            raid_disks: 2,
+            md_minor: 0,
+            not_persistent: 0,
+            utime: 0x66bb28b3,
+            state: 1,
+            active_disks: 2,
+            working_disks: 2,
+            failed_disks: 0,
+            spare_disks: 0,
+            sb_csum: 0,
+            events: 0x1_0000_0012,
+            cp_events: 0x12,
+            recovery_cp: u32::MAX,
+            reshape_position: 0,
+            new_level: 0,
+            delta_disks: 0,
+            new_layout: 0,
+            new_chunk: 0,
+            layout: 0,
+            chunk_size: 0,
+            disks,
+            this_disk: disks[1],
+        };
+        sb.sb_csum = sb.calculate_sb_csum();
+        sb
+    }
+
+    #[test]
+    fn test_checksum_covers_reserved_words() {
+        let sb = example_superblock(ByteOrder::Little);
+        let mut bytes = sb.as_bytes();
+        // a reserved word in the generic constant section
+        bytes[20 * 4] = 1;
+        let err = MdpSuperblock090::from_bytes(&bytes).unwrap_err();
+        assert!(matches!(
+            err,
+            MdError::ChecksumMismatch { expected, computed }
+                if expected == sb.sb_csum && computed == sb.sb_csum + 1
+        ));
+        assert!(MdpSuperblock090::from_bytes_lenient(&bytes).is_ok());
+    }
+
+    #[test]
+    fn test_roundtrip_both_byte_orders() {
+        for order in [ByteOrder::Little, ByteOrder::Big] {
+            let sb = example_superblock(order);
+            let bytes = sb.as_bytes();
+            let parsed = MdpSuperblock090::from_bytes(&bytes).unwrap();
+            assert_eq!(parsed.byte_order, order);
+            assert_eq!(parsed.events, 0x1_0000_0012);
+            assert_eq!(parsed.this_disk, sb.this_disk);
+            assert_eq!(parsed.disks, sb.disks);
+            assert_eq!(parsed.sb_csum, parsed.calculate_sb_csum());
+            assert_eq!(parsed.as_bytes(), bytes);
+            assert_eq!(
+                parsed.uuid(),
+                Uuid::parse_str("24d684dd-bc67-60fc-a5d3-a49f592b1b42").unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_byte_orders_are_word_swapped() {
+        let le = example_superblock(ByteOrder::Little).as_bytes();
+        let be = example_superblock(ByteOrder::Big).as_bytes();
+        assert_eq!(&le[0..4], &[0xfc, 0x4e, 0x2b, 0xa9]);
+        assert_eq!(&be[0..4], &[0xa9, 0x2b, 0x4e, 0xfc]);
+        // events_lo is the first word on little endian, events_hi on big endian
+        assert_eq!(&le[156..164], &[0x12, 0, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(&be[156..164], &[0, 0, 0, 1, 0, 0, 0, 0x12]);
+    }
+
+    #[test]
+    fn test_offset_at_end_of_device() {
+        // 10MiB is already 64KiB aligned, the superblock is in the last 64KiB
+        assert_eq!(
+            MdpSuperblock090::offset(10 * 1024 * 1024),
+            Some(10 * 1024 * 1024 - 64 * 1024)
+        );
+        // the partial 64KiB at the end is not used
+        assert_eq!(
+            MdpSuperblock090::offset(10 * 1024 * 1024 + 4096),
+            Some(10 * 1024 * 1024 - 64 * 1024)
+        );
+        assert_eq!(MdpSuperblock090::offset(32 * 1024), None);
+    }
+
+    #[test]
+    fn test_probe_end_of_device() {
+        let size = 1024 * 1024;
+        let sb = example_superblock(ByteOrder::Big);
+        let offset = MdpSuperblock090::offset(size).unwrap() as usize;
+        let mut image = vec![0u8; size as usize];
+        image[offset..offset + MD_SB_BYTES].copy_from_slice(&sb.as_bytes());
+        let path = std::env::temp_dir().join(format!("probe_v0_90-{}", std::process::id()));
+        std::fs::write(&path, image).unwrap();
+
+        let probed = crate::Superblock::probe(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        match probed.unwrap() {
+            crate::Superblock::V0_90(probed) => assert_eq!(probed.uuid(), sb.uuid()),
+            other => panic!("expected a 0.90 superblock, got {other:?}"),
+        }
+    }
+}
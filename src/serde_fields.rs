@@ -0,0 +1,165 @@
+//! `serde(with)` helpers that store superblock fields as decoded values
+//! instead of their on-disk representation.
+use crate::{
+    instant_to_arrayinfo_format, sb_time, str_to_bytes, ArrayLevel, DeviceRole, FeatureMap,
+};
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serializer};
+use std::fmt;
+use uuid::Uuid;
+
+/// `[u8; 16]` as a hyphenated uuid string
+pub(crate) mod uuid_bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8; 16], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(&Uuid::from_bytes(*bytes))
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<[u8; 16], D::Error> {
+        let s = String::deserialize(d)?;
+        let uuid = Uuid::parse_str(&s).map_err(de::Error::custom)?;
+        Ok(uuid.into_bytes())
+    }
+}
+
+/// NUL padded `set_name` as a string
+pub(crate) mod set_name {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8; 32], s: S) -> Result<S::Ok, S::Error> {
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        s.serialize_str(&String::from_utf8_lossy(&bytes[..len]))
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<[u8; 32], D::Error> {
+        let s = String::deserialize(d)?;
+        if s.len() > 32 {
+            return Err(de::Error::invalid_length(s.len(), &"at most 32 bytes"));
+        }
+        Ok(str_to_bytes(&s))
+    }
+}
+
+/// ctime/utime as an RFC 3339 timestamp in UTC, with microseconds
+pub(crate) mod timestamp {
+    use super::*;
+    use chrono::{DateTime, SecondsFormat, Utc};
+
+    pub fn serialize<S: Serializer>(time: &u64, s: S) -> Result<S::Ok, S::Error> {
+        let time = sb_time(*time).and_utc();
+        s.serialize_str(&time.to_rfc3339_opts(SecondsFormat::Micros, true))
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+        let s = String::deserialize(d)?;
+        let time = DateTime::parse_from_rfc3339(&s).map_err(de::Error::custom)?;
+        Ok(instant_to_arrayinfo_format(time.with_timezone(&Utc)))
+    }
+}
+
+/// Raid level by name ("raid5", "linear"), or the raw number for levels
+/// without one
+pub(crate) mod level {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(level: &u32, s: S) -> Result<S::Ok, S::Error> {
+        match ArrayLevel::from_raw(*level as i32) {
+            Some(level) => s.collect_str(&level),
+            None => s.serialize_i32(*level as i32),
+        }
+    }
+
+    struct LevelVisitor;
+
+    impl Visitor<'_> for LevelVisitor {
+        type Value = u32;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a raid level name or number")
+        }
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<u32, E> {
+            Ok(v as i32 as u32)
+        }
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<u32, E> {
+            Ok(v as u32)
+        }
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<u32, E> {
+            v.parse::<ArrayLevel>()
+                .map(u32::from)
+                .map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self))
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<u32, D::Error> {
+        d.deserialize_any(LevelVisitor)
+    }
+}
+
+/// feature_map as flag names, see `bitflags::serde`
+pub(crate) mod feature_map {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bits: &u32, s: S) -> Result<S::Ok, S::Error> {
+        bitflags::serde::serialize(&FeatureMap::from_bits_retain(*bits), s)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<u32, D::Error> {
+        let features: FeatureMap = bitflags::serde::deserialize(d)?;
+        Ok(features.bits())
+    }
+}
+
+/// Fields stored as u32 that hold a signed value
+pub(crate) mod signed {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(v: &u32, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_i32(*v as i32)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<u32, D::Error> {
+        Ok(i32::deserialize(d)? as u32)
+    }
+}
+
+/// dev_roles as `DeviceRole`s. Reserved values, which `DeviceRole` reads as
+/// spares, are kept as `{"unknown": n}` so they survive a round trip
+pub(crate) mod dev_roles {
+    use super::*;
+    use serde::Serialize;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Role {
+        Known(DeviceRole),
+        Unknown { unknown: u16 },
+    }
+
+    impl From<u16> for Role {
+        fn from(raw: u16) -> Self {
+            let role = DeviceRole::from(raw);
+            if u16::from(role) == raw {
+                Role::Known(role)
+            } else {
+                Role::Unknown { unknown: raw }
+            }
+        }
+    }
+
+    impl From<Role> for u16 {
+        fn from(role: Role) -> Self {
+            match role {
+                Role::Known(role) => role.into(),
+                Role::Unknown { unknown } => unknown,
+            }
+        }
+    }
+
+    pub fn serialize<S: Serializer>(roles: &[u16], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(roles.iter().map(|&role| Role::from(role)))
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u16>, D::Error> {
+        let roles = Vec::<Role>::deserialize(d)?;
+        Ok(roles.into_iter().map(u16::from).collect())
+    }
+}
+
+pub(crate) fn is_zero(bytes: &[u8; 32]) -> bool {
+    bytes.iter().all(|&b| b == 0)
+}
@@ -0,0 +1,103 @@
+//! Wiping md metadata from devices that are no longer members of an array.
+use crate::sb090::{MdpSuperblock090, MD_RESERVED_SECTORS, MD_SB_BITMAP_PRESENT, MD_SB_BYTES};
+use crate::{
+    block, BitmapSuperblock, MdError, MdpSuperblock1, Result, Superblock, SuperblockVersion,
+};
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Zeros are written this many bytes at a time
+const ZERO_BLOCK: usize = 64 * 1024;
+
+/// Zero every md superblock on `path` (0.90 and all 1.x locations), along with
+/// its bitmap and bad block log. Only locations holding the md magic are
+/// touched, superblocks with a bad checksum are wiped too.
+///
+/// Block devices are opened exclusively, so members of a running array are
+/// refused with `DeviceBusy`. Returns the superblocks that were wiped.
+pub fn zero_superblock(path: &str) -> Result<Vec<Superblock>> {
+    let mut file = block::open_exclusive(Path::new(path))?;
+    let found = wipe_metadata(&mut file, path)?;
+    if found.is_empty() {
+        return Err(MdError::NoSuperblock {
+            path: path.to_string(),
+        });
+    }
+    file.sync_all()?;
+    Ok(found)
+}
+
+/// Zero the md metadata of every version found on `file`, opened from `path`.
+/// Returns the superblocks that were wiped, none if there weren't any. Also
+/// used by `create`, so a forced create leaves no other version behind
+pub(crate) fn wipe_metadata(file: &mut File, path: &str) -> Result<Vec<Superblock>> {
+    let device_size = file.seek(SeekFrom::End(0))?;
+
+    let mut found = Vec::new();
+    // byte ranges to zero
+    let mut regions = Vec::new();
+    for version in SuperblockVersion::ALL {
+        let Some(super_offset) = version.super_offset(device_size / 512) else {
+            continue;
+        };
+        let sb = match MdpSuperblock1::from_file_lenient(path, super_offset * 512) {
+            // a superblock records where it was written, other matches are
+            // leftovers of a different version and found at their own location
+            Ok(sb) if sb.device_info.super_offset == super_offset => sb,
+            _ => continue,
+        };
+        regions.push((super_offset * 512, MdpSuperblock1::MAX_SIZE as u64));
+        if let (Some(location), Some(room)) = (sb.bitmap_location(), sb.bitmap_room()) {
+            regions.push((location, bitmap_len(path, location, room * 512)));
+        }
+        if let Some(location) = sb.bblog_location() {
+            regions.push((location, sb.device_info.bblog_size as u64 * 512));
+        }
+        found.push(Superblock::V1(version, sb));
+    }
+    if let Some(offset) = MdpSuperblock090::offset(device_size) {
+        if let Ok(sb) = MdpSuperblock090::from_file_lenient(path, offset) {
+            regions.push((offset, MD_SB_BYTES as u64));
+            if sb.state & (1 << MD_SB_BITMAP_PRESENT) != 0 {
+                // the bitmap fills the rest of the reserved area
+                let location = offset + MD_SB_BYTES as u64;
+                let room = MD_RESERVED_SECTORS * 512 - MD_SB_BYTES as u64;
+                regions.push((location, bitmap_len(path, location, room)));
+            }
+            found.push(Superblock::V0_90(sb));
+        }
+    }
+
+    for (offset, len) in regions {
+        // never write past the end, a corrupt superblock can point anywhere
+        let len = len.min(device_size.saturating_sub(offset));
+        zero_range(file, offset, len)?;
+    }
+    Ok(found)
+}
+
+/// Bytes used by the bitmap at `location`, just its superblock if it can't be
+/// read. Never more than the `room` the layout reserves for it, a corrupt
+/// bitmap superblock must not reach into the data
+fn bitmap_len(path: &str, location: u64, room: u64) -> u64 {
+    let len = match BitmapSuperblock::from_file(path, location) {
+        Ok(bitmap) => {
+            (BitmapSuperblock::SIZE as u64 + bitmap.bits_len() as u64).next_multiple_of(4096)
+        }
+        Err(_) => BitmapSuperblock::SIZE as u64,
+    };
+    len.min(room)
+}
+
+fn zero_range(file: &mut File, offset: u64, len: u64) -> Result<()> {
+    let zeros = [0; ZERO_BLOCK];
+    file.seek(SeekFrom::Start(offset))?;
+    let mut left = len;
+    while left > 0 {
+        let n = left.min(ZERO_BLOCK as u64) as usize;
+        file.write_all(&zeros[..n])?;
+        left -= n as u64;
+    }
+    Ok(())
+}
@@ -1,5 +1,7 @@
-use device_mapper::MdpSuperblock1;
-use flate2;
+use device_mapper::{
+    create::ArrayBuilder, zero::zero_superblock, ArrayLevel, BadBlockLog, DeviceRole, FeatureMap,
+    MdError, MdpSuperblock090, MdpSuperblock1, Superblock, SuperblockVersion,
+};
 use flate2::read::GzDecoder;
 use std::io::prelude::*;
 use uuid::Uuid;
@@ -8,9 +10,18 @@ fn read_gzipped_superblock(path: &str) -> MdpSuperblock1 {
     let compressed = std::fs::read(path).unwrap();
     let mut d = GzDecoder::new(compressed.as_slice());
     let mut buf = vec![0; 4096 + MdpSuperblock1::MAX_SIZE]; // first 4KiB are empty
-    d.read(&mut buf).unwrap();
-    let sb = MdpSuperblock1::from_bytes(&buf[0x1000..]).unwrap();
-    sb
+    d.read_exact(&mut buf).unwrap();
+    MdpSuperblock1::from_bytes(&buf[0x1000..]).unwrap()
+}
+
+fn gunzip_to_tempfile(path: &str, name: &str) -> std::path::PathBuf {
+    let compressed = std::fs::read(path).unwrap();
+    let mut d = GzDecoder::new(compressed.as_slice());
+    let mut image = Vec::new();
+    d.read_to_end(&mut image).unwrap();
+    let out = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+    std::fs::write(&out, image).unwrap();
+    out
 }
 
 #[test]
@@ -96,6 +107,7 @@ fn test_feature_map() {
     let sb = read_gzipped_superblock("tests/testdata/r1_d1.gz");
     let fm = sb.array_info.feature_map;
     assert_eq!(fm, 0);
+    assert_eq!(sb.array_info.features(), FeatureMap::empty());
 }
 
 #[test]
@@ -113,61 +125,361 @@ fn test_events_count() {
     assert_eq!(sb1.array_state_info.events, 16);
     assert_eq!(sb2.array_state_info.events, 16);
 }
-/*
-TZ=UTC mdadm --examine testdata/r1_d1
-r1_d1:
-          Magic : a92b4efc
-        Version : 1.2
-    Feature Map : 0x0
-     Array UUID : 24d684dd:bc6760fc:a5d3a49f:592b1b42
-           Name : worklaptop:0  (local to host worklaptop)
-  Creation Time : Tue Aug 13 09:34:43 2024
-     Raid Level : raid1
-   Raid Devices : 2
-
- Avail Dev Size : 18432 sectors (9.00 MiB 9.44 MB)
-     Array Size : 9216 KiB (9.00 MiB 9.44 MB)
-    Data Offset : 2048 sectors
-   Super Offset : 8 sectors
-   Unused Space : before=1968 sectors, after=0 sectors
-          State : clean
-    Device UUID : 201e03cf:4205c8bf:e71452f8:68f6b6cd
-
-    Update Time : Tue Aug 13 09:34:43 2024
-  Bad Block Log : 512 entries available at offset 16 sectors
-       Checksum : 9741e5f7 - correct
-         Events : 16
-
-
-   Device Role : Active device 0
-   Array State : AA ('A' == active, '.' == missing, 'R' == replacing)
-*/
-
-/*
-r1_d2:
-          Magic : a92b4efc
-        Version : 1.2
-    Feature Map : 0x0
-     Array UUID : 24d684dd:bc6760fc:a5d3a49f:592b1b42
-           Name : worklaptop:0  (local to host worklaptop)
-  Creation Time : Tue Aug 13 09:34:43 2024
-     Raid Level : raid1
-   Raid Devices : 2
-
- Avail Dev Size : 18432 sectors (9.00 MiB 9.44 MB)
-     Array Size : 9216 KiB (9.00 MiB 9.44 MB)
-    Data Offset : 2048 sectors
-   Super Offset : 8 sectors
-   Unused Space : before=1968 sectors, after=0 sectors
-          State : clean
-    Device UUID : fc9b0876:925c3729:5f47971a:f9ce24fc
-
-    Update Time : Tue Aug 13 09:34:43 2024
-  Bad Block Log : 512 entries available at offset 16 sectors
-       Checksum : f869c62b - correct
-         Events : 16
-
-
-   Device Role : Active device 1
-   Array State : AA ('A' == active, '.' == missing, 'R' == replacing)
-*/
+
+#[test]
+fn test_serialization_matches_disk() {
+    for path in ["tests/testdata/r1_d1.gz", "tests/testdata/r1_d2.gz"] {
+        let compressed = std::fs::read(path).unwrap();
+        let mut d = GzDecoder::new(compressed.as_slice());
+        let mut buf = vec![0; 4096 + MdpSuperblock1::MAX_SIZE];
+        d.read_exact(&mut buf).unwrap();
+        let on_disk = &buf[0x1000..];
+
+        let sb = MdpSuperblock1::from_bytes(on_disk).unwrap();
+        let bytes = sb.as_bytes();
+        assert_eq!(bytes, on_disk[..bytes.len()]);
+    }
+}
+
+#[test]
+fn test_device_roles() {
+    let sb1 = read_gzipped_superblock("tests/testdata/r1_d1.gz");
+    let sb2 = read_gzipped_superblock("tests/testdata/r1_d2.gz");
+
+    // "Device Role : Active device 0" / "Active device 1"
+    assert_eq!(sb1.role(), DeviceRole::Active(0));
+    assert_eq!(sb2.role(), DeviceRole::Active(1));
+    assert_eq!(sb1.role_of(2), DeviceRole::Spare);
+    assert_eq!(sb1.array_state(), "AA");
+    assert_eq!(sb2.array_state(), "AA");
+}
+
+#[test]
+fn test_probe_finds_v1_2() {
+    let image = gunzip_to_tempfile("tests/testdata/r1_d1.gz", "probe_r1_d1");
+    let (version, sb) = MdpSuperblock1::probe(image.to_str().unwrap()).unwrap();
+    std::fs::remove_file(&image).unwrap();
+
+    assert_eq!(version, SuperblockVersion::V1_2);
+    assert_eq!(sb.version(), SuperblockVersion::V1_2);
+    assert_eq!(
+        sb.device_info.uuid(),
+        Uuid::parse_str("201e03cf-4205-c8bf-e714-52f868f6b6cd").unwrap()
+    );
+}
+
+#[test]
+fn test_probe_reports_corrupt_superblock() {
+    let image = gunzip_to_tempfile("tests/testdata/r1_d1.gz", "corrupt_r1_d1");
+    let path = image.to_str().unwrap();
+    let mut bytes = std::fs::read(path).unwrap();
+    // first byte of the array name, at superblock offset 32
+    bytes[0x1000 + 32] ^= 1;
+    std::fs::write(path, &bytes).unwrap();
+
+    let err = MdpSuperblock1::probe(path).unwrap_err();
+    let lenient = MdpSuperblock1::from_file_lenient(path, 0x1000);
+    std::fs::remove_file(&image).unwrap();
+
+    let MdError::ChecksumMismatch { expected, computed } = err else {
+        panic!("expected a checksum mismatch, got {err}");
+    };
+    assert_eq!(expected, 0x9741e5f7);
+    assert_ne!(computed, expected);
+    assert_eq!(lenient.unwrap().array_state_info.sb_csum, 0x9741e5f7);
+}
+
+#[test]
+fn test_bad_block_log_edit() {
+    let image = gunzip_to_tempfile("tests/testdata/r1_d1.gz", "bblog_r1_d1");
+    let path = image.to_str().unwrap();
+    let (_, mut sb) = MdpSuperblock1::probe(path).unwrap();
+    // 512 entries available at offset 16 sectors
+    assert_eq!(sb.bblog_location(), Some((8 + 16) * 512));
+
+    let mut log = BadBlockLog::from_superblock(path, &sb).unwrap();
+    assert!(log.is_empty());
+    log.add(4096, 16);
+    log.add(100, 1);
+    log.write(path, &mut sb).unwrap();
+
+    let (_, sb) = MdpSuperblock1::probe(path).unwrap();
+    assert!(sb.array_info.features().contains(FeatureMap::BAD_BLOCKS));
+    assert_eq!(sb.calculate_sb_csum(), sb.array_state_info.sb_csum);
+    let mut log = BadBlockLog::from_superblock(path, &sb).unwrap();
+    let ranges: Vec<(u64, u64, bool)> = log
+        .entries()
+        .iter()
+        .map(|bb| (bb.sector, bb.length, bb.acknowledged))
+        .collect();
+    assert_eq!(ranges, vec![(100, 1, true), (4096, 16, true)]);
+
+    // clearing every entry drops the feature bit again
+    log.clear(0, u64::MAX / 2);
+    let mut sb = sb;
+    log.write(path, &mut sb).unwrap();
+    let (_, sb) = MdpSuperblock1::probe(path).unwrap();
+    std::fs::remove_file(&image).unwrap();
+    assert_eq!(sb.array_info.features(), FeatureMap::empty());
+}
+
+#[test]
+fn test_force_event_count() {
+    let image = gunzip_to_tempfile("tests/testdata/r1_d2.gz", "events_r1_d2");
+    let path = image.to_str().unwrap();
+    let mut sb = Superblock::probe(path).unwrap();
+    let events = sb.events();
+    sb.set_events(events + 10);
+    sb.write(path).unwrap();
+
+    let sb = Superblock::probe(path).unwrap();
+    std::fs::remove_file(&image).unwrap();
+    // probe verifies the checksum
+    assert_eq!(sb.events(), events + 10);
+    assert_eq!(sb.role(), DeviceRole::Active(1));
+}
+
+fn empty_tempfile(name: &str, size: u64) -> std::path::PathBuf {
+    let out = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+    std::fs::File::create(&out).unwrap().set_len(size).unwrap();
+    out
+}
+
+#[test]
+fn test_builder_refuses_existing_metadata() {
+    let images: Vec<_> = (0..2)
+        .map(|i| empty_tempfile(&format!("create_{i}"), 4 << 20))
+        .collect();
+    let paths: Vec<&str> = images.iter().map(|p| p.to_str().unwrap()).collect();
+    let uuid = Uuid::new_v4();
+    let builder = ArrayBuilder::new(ArrayLevel::Raid1, "0")
+        .homehost("worklaptop")
+        .uuid(uuid)
+        .internal_bitmap(true);
+    let created = builder.create(&paths).unwrap();
+
+    for (i, path) in paths.iter().enumerate() {
+        let (version, sb) = MdpSuperblock1::probe(path).unwrap();
+        assert_eq!(version, SuperblockVersion::V1_2);
+        assert_eq!(sb.as_bytes(), created[i].as_bytes());
+        assert_eq!(sb.array_info.uuid(), uuid);
+        assert_eq!(sb.array_info.name().unwrap(), "worklaptop:0");
+        assert_eq!(sb.role(), DeviceRole::Active(i as u16));
+    }
+
+    let err = builder.create(&paths).unwrap_err();
+    let again = builder.clone().force(true).create(&paths);
+
+    // a filesystem signature is refused as well
+    let ext = empty_tempfile("create_ext", 4 << 20);
+    let mut bytes = std::fs::read(&ext).unwrap();
+    bytes[1080..1082].copy_from_slice(&[0x53, 0xef]);
+    std::fs::write(&ext, &bytes).unwrap();
+    let ext_err = builder.create(&[ext.to_str().unwrap()]).unwrap_err();
+
+    for image in images.iter().chain([&ext]) {
+        std::fs::remove_file(image).unwrap();
+    }
+    let MdError::ExistingSignature { path, what } = err else {
+        panic!("expected an existing signature, got {err}");
+    };
+    assert_eq!(path, paths[0]);
+    assert_eq!(what, "an md superblock (version 1.2)");
+    assert!(again.is_ok());
+    let MdError::ExistingSignature { what, .. } = ext_err else {
+        panic!("expected an existing signature, got {ext_err}");
+    };
+    assert_eq!(what, "an ext2/3/4 filesystem");
+}
+
+#[test]
+fn test_forced_create_over_other_version() {
+    let image = empty_tempfile("create_over", 4 << 20);
+    let path = image.to_str().unwrap();
+    let builder = ArrayBuilder::new(ArrayLevel::Raid1, "0")
+        .homehost("worklaptop")
+        .internal_bitmap(true);
+    let old = builder.create(&[path]).unwrap();
+    let new = builder
+        .metadata(SuperblockVersion::V1_0)
+        .force(true)
+        .create(&[path])
+        .unwrap();
+
+    let probed = Superblock::probe(path);
+    let bytes = std::fs::read(&image).unwrap();
+    let zeroed = zero_superblock(path).unwrap();
+    std::fs::remove_file(&image).unwrap();
+
+    assert_eq!(probed.unwrap().minor_version(), 0);
+    // the 1.2 superblock, its bitmap and bad block log are gone
+    let old_bblog = old[0].bblog_location().unwrap() as usize + 4096;
+    assert!(bytes[..old_bblog].iter().all(|&b| b == 0));
+    let versions: Vec<_> = zeroed.iter().map(|sb| sb.minor_version()).collect();
+    assert_eq!(versions, vec![0]);
+    assert_eq!(zeroed[0].uuid(), new[0].array_info.uuid());
+}
+
+#[test]
+fn test_zero_superblock() {
+    let images: Vec<_> = [SuperblockVersion::V1_0, SuperblockVersion::V1_2]
+        .iter()
+        .map(|version| {
+            let image = empty_tempfile(&format!("zero_{version:?}"), 4 << 20);
+            ArrayBuilder::new(ArrayLevel::Raid1, "0")
+                .homehost("worklaptop")
+                .metadata(*version)
+                .internal_bitmap(true)
+                .create(&[image.to_str().unwrap()])
+                .unwrap();
+            image
+        })
+        .collect();
+    // 1.0 and 1.2 superblocks on the same device, the latter with a bad checksum
+    let path = images[0].to_str().unwrap();
+    let mut bytes = std::fs::read(path).unwrap();
+    let mut v1_2 = std::fs::read(&images[1]).unwrap();
+    v1_2[0x1000 + 32] ^= 1;
+    bytes[..1 << 20].copy_from_slice(&v1_2[..1 << 20]);
+    std::fs::write(path, &bytes).unwrap();
+
+    let zeroed = zero_superblock(path).unwrap();
+    let after = std::fs::read(path).unwrap();
+    let again = zero_superblock(path).unwrap_err();
+    for image in &images {
+        std::fs::remove_file(image).unwrap();
+    }
+
+    let versions: Vec<_> = zeroed
+        .iter()
+        .map(|sb| (sb.major_version(), sb.minor_version()))
+        .collect();
+    assert_eq!(versions, vec![(1, 2), (1, 0)]);
+    // superblocks and bitmaps were the only things written
+    assert!(after.iter().all(|&b| b == 0));
+    assert!(matches!(again, MdError::NoSuperblock { .. }));
+}
+
+#[test]
+fn test_zero_superblock_keeps_data() {
+    let image = empty_tempfile("zero_data", 4 << 20);
+    let path = image.to_str().unwrap();
+    let sb = ArrayBuilder::new(ArrayLevel::Raid1, "0")
+        .homehost("worklaptop")
+        .internal_bitmap(true)
+        .create(&[path])
+        .unwrap()
+        .remove(0);
+    // a bitmap claiming to cover far more than the array, and data after it
+    let mut bytes = std::fs::read(&image).unwrap();
+    let bitmap = sb.bitmap_location().unwrap() as usize;
+    bytes[bitmap + 40..bitmap + 48].copy_from_slice(&(1u64 << 40).to_le_bytes());
+    let data_offset = sb.device_info.data_offset as usize * 512;
+    bytes[data_offset..].fill(0xaa);
+    std::fs::write(&image, &bytes).unwrap();
+
+    zero_superblock(path).unwrap();
+    let after = std::fs::read(&image).unwrap();
+    std::fs::remove_file(&image).unwrap();
+
+    assert!(after[..data_offset].iter().all(|&b| b == 0));
+    assert!(after[data_offset..].iter().all(|&b| b == 0xaa));
+}
+
+#[test]
+fn test_zero_superblock_without_bblog() {
+    for version in [SuperblockVersion::V1_2, SuperblockVersion::V1_0] {
+        let image = empty_tempfile(&format!("zero_no_bblog_{version:?}"), 4 << 20);
+        let path = image.to_str().unwrap();
+        let mut sb = ArrayBuilder::new(ArrayLevel::Raid1, "0")
+            .homehost("worklaptop")
+            .metadata(version)
+            .internal_bitmap(true)
+            .create(&[path])
+            .unwrap()
+            .remove(0);
+        // as written by mdadm before bad block logs
+        sb.device_info.bblog_offset = 0;
+        sb.device_info.bblog_size = 0;
+        Superblock::V1(version, sb).write(path).unwrap();
+
+        zero_superblock(path).unwrap();
+        let after = std::fs::read(&image).unwrap();
+        std::fs::remove_file(&image).unwrap();
+        assert!(after.iter().all(|&b| b == 0), "{version:?}");
+    }
+}
+
+/// Compare against `TZ=UTC mdadm --examine`, captured in tests/testdata/<name>.examine
+fn assert_examine_matches(name: &str) {
+    let sb = read_gzipped_superblock(&format!("tests/testdata/{name}.gz"));
+    let expected = std::fs::read_to_string(format!("tests/testdata/{name}.examine")).unwrap();
+    // the images are 10MiB
+    let out = format!(
+        "{name}:\n{}",
+        sb.examine(20480, Some("worklaptop"), chrono::Utc)
+    );
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn test_examine_matches_mdadm() {
+    assert_examine_matches("r1_d1");
+    assert_examine_matches("r1_d2");
+}
+
+#[test]
+fn test_examine_0_90() {
+    // a 3 disk raid5 with a spare, laid out as mdadm's examine_super0 prints
+    // it, minus the device names it looks up for each descriptor
+    let image = gunzip_to_tempfile("tests/testdata/r5_090.gz", "examine_r5_090");
+    let probed = MdpSuperblock090::probe(image.to_str().unwrap());
+    std::fs::remove_file(&image).unwrap();
+    let expected = std::fs::read_to_string("tests/testdata/r5_090.examine").unwrap();
+    let out = format!("r5_090:\n{}", probed.unwrap().examine(chrono::Utc));
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn test_examine_other_host() {
+    let sb = read_gzipped_superblock("tests/testdata/r1_d1.gz");
+    let out = sb
+        .examine(20480, Some("otherhost"), chrono::Utc)
+        .to_string();
+    assert!(out.contains("           Name : worklaptop:0\n"));
+
+    let mut sb = sb;
+    sb.array_state_info.events += 1;
+    let out = sb.examine(20480, None, chrono::Utc).to_string();
+    assert!(out.contains("       Checksum : 9741e5f7 - expected "));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_json_roundtrip() {
+    let sb = read_gzipped_superblock("tests/testdata/r1_d1.gz");
+    let json = serde_json::to_value(&sb).unwrap();
+    assert_eq!(
+        json["array_info"]["uuid"],
+        "24d684dd-bc67-60fc-a5d3-a49f592b1b42"
+    );
+    assert_eq!(json["array_info"]["name"], "worklaptop:0");
+    assert_eq!(json["array_info"]["level"], "raid1");
+    assert_eq!(json["array_info"]["feature_map"], "");
+    assert_eq!(json["array_info"]["ctime"], "2024-08-13T09:34:43.000000Z");
+    assert_eq!(json["device_info"]["bblog_offset"], 16);
+    assert_eq!(json["dev_roles"][0], serde_json::json!({"active": 0}));
+    assert_eq!(json["dev_roles"][1], serde_json::json!({"active": 1}));
+    assert_eq!(json["dev_roles"][2], "spare");
+
+    let parsed: MdpSuperblock1 = serde_json::from_value(json).unwrap();
+    assert_eq!(parsed.as_bytes(), sb.as_bytes());
+
+    // reserved role values are kept as they are
+    let mut sb = sb;
+    sb.dev_roles[3] = 0xff10;
+    let json = serde_json::to_value(&sb).unwrap();
+    assert_eq!(json["dev_roles"][3], serde_json::json!({"unknown": 0xff10}));
+    let parsed: MdpSuperblock1 = serde_json::from_value(json).unwrap();
+    assert_eq!(parsed.dev_roles, sb.dev_roles);
+}